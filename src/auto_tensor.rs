@@ -0,0 +1,135 @@
+//! Runtime choice between the GPU and CPU tensorizers.
+
+use std::sync::{Arc, RwLock};
+
+use image::DynamicImage;
+use ndarray::{Array3, Array4};
+
+use crate::{
+    cpu_tensor::{CpuTensorizer, ImageConvert},
+    element::{QuantElement, QuantParams, TensorElement},
+    error::{Result, TensorizeError},
+    gpu_context::GpuOptions,
+    gpu_tensor::GpuTensorizer,
+    tensorizer_trait::Tensorizer,
+};
+
+/// The implementation an [`AutoTensorizer`] currently runs on.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum Backend {
+    Gpu,
+    Cpu,
+}
+
+/// Tensorizes on the GPU when one is available and on the CPU otherwise.
+///
+/// If no adapter or device can be obtained at construction, or a lost device
+/// cannot be replaced later on, it switches to a [`CpuTensorizer`] with the same
+/// [`ImageConvert`] for good. The two backends use slightly different
+/// filters, so results can differ in the last decimals.
+pub struct AutoTensorizer {
+    gpu: RwLock<Option<Arc<GpuTensorizer>>>,
+    cpu: CpuTensorizer,
+}
+
+/// Errors that mean the GPU is unusable rather than the request being wrong.
+fn is_gpu_unavailable(err: &TensorizeError) -> bool {
+    matches!(
+        err,
+        TensorizeError::NoAdapter(_)
+            | TensorizeError::AdapterNotFound(_)
+            | TensorizeError::RequestDevice(_)
+            | TensorizeError::DeviceLost(_)
+    )
+}
+
+impl Tensorizer for AutoTensorizer {
+    type BuildType = AutoTensorizer;
+
+    async fn new(config: ImageConvert) -> Result<Self::BuildType> {
+        AutoTensorizer::with_options(config, GpuOptions::default()).await
+    }
+
+    fn config(&self) -> &ImageConvert {
+        self.cpu.config()
+    }
+
+    async fn tensorize_as<T: TensorElement>(&self, image: &DynamicImage) -> Result<Array3<T>> {
+        if let Some(gpu) = self.gpu() {
+            match gpu.tensorize_as(image).await {
+                Err(err) => self.fall_back(err)?,
+                result => return result,
+            }
+        }
+        self.cpu.tensorize_as(image).await
+    }
+
+    async fn tensorize_quantized<Q: QuantElement>(
+        &self,
+        image: &DynamicImage,
+        params: QuantParams,
+    ) -> Result<Array3<Q>> {
+        if let Some(gpu) = self.gpu() {
+            match gpu.tensorize_quantized(image, params).await {
+                Err(err) => self.fall_back(err)?,
+                result => return result,
+            }
+        }
+        self.cpu.tensorize_quantized(image, params).await
+    }
+
+    async fn tensorize_batch_as<T: TensorElement>(
+        &self,
+        images: &[DynamicImage],
+    ) -> Result<Array4<T>> {
+        if let Some(gpu) = self.gpu() {
+            match gpu.tensorize_batch_as(images).await {
+                Err(err) => self.fall_back(err)?,
+                result => return result,
+            }
+        }
+        self.cpu.tensorize_batch_as(images).await
+    }
+}
+
+impl AutoTensorizer {
+    /// Tries the adapter described by `options` before falling back to the CPU.
+    pub async fn with_options(config: ImageConvert, options: GpuOptions) -> Result<Self> {
+        let cpu = CpuTensorizer::new(config).await?;
+        let gpu = match GpuTensorizer::with_options(config, options).await {
+            Ok(gpu) => Some(Arc::new(gpu)),
+            Err(err) if is_gpu_unavailable(&err) => {
+                log::warn!("no usable GPU, tensorizing on the CPU: {err}");
+                None
+            }
+            Err(err) => return Err(err),
+        };
+        Ok(AutoTensorizer {
+            gpu: RwLock::new(gpu),
+            cpu,
+        })
+    }
+
+    /// The backend the next call will run on.
+    pub fn backend(&self) -> Backend {
+        match *self.gpu.read().unwrap() {
+            Some(_) => Backend::Gpu,
+            None => Backend::Cpu,
+        }
+    }
+
+    /// The GPU tensorizer, unless running on the CPU.
+    pub fn gpu(&self) -> Option<Arc<GpuTensorizer>> {
+        self.gpu.read().unwrap().clone()
+    }
+
+    /// Switches to the CPU if `err` means the GPU is gone, otherwise returns it.
+    fn fall_back(&self, err: TensorizeError) -> Result<()> {
+        if !is_gpu_unavailable(&err) {
+            return Err(err);
+        }
+        log::warn!("GPU failed, tensorizing on the CPU from now on: {err}");
+        *self.gpu.write().unwrap() = None;
+        Ok(())
+    }
+}
@@ -1,7 +1,12 @@
-use image::DynamicImage;
+use image::{DynamicImage, GrayImage, RgbImage, Rgba, RgbaImage};
 use ndarray::{Array3, Array4, Dim};
 
-use crate::tensorizer_trait::Tensorizer;
+use crate::{
+    element::{QuantElement, QuantParams, TensorElement},
+    error::{Result, TensorizeError},
+    letterbox::LetterboxInfo,
+    tensorizer_trait::Tensorizer,
+};
 
 pub const IMAGENET_DEFAULT_MEAN: [f32; 3] = [0.485, 0.456, 0.406];
 pub const IMAGENET_DEFAULT_STD: [f32; 3] = [0.229, 0.224, 0.225];
@@ -9,31 +14,225 @@ pub const IMAGENET_DEFAULT_CONFIG: ImageConvert = ImageConvert {
     channels: 3,
     width: 256,
     height: 256,
-    crop: 224,
+    crop_width: 224,
+    crop_height: 224,
     mean: IMAGENET_DEFAULT_MEAN,
     std: IMAGENET_DEFAULT_STD,
     interpolation: image::imageops::FilterType::CatmullRom,
+    resize_mode: ResizeMode::Exact,
+    fill: [0; 3],
+    layout: Layout::Chw,
+    channel_order: ChannelOrder::Rgb,
+    input_scale: 1.0 / 255.0,
 };
 
 pub const IMAGENET_DEFAULT_CONFIG_NO_CROP: ImageConvert = ImageConvert {
     channels: 3,
     width: 224,
     height: 224,
-    crop: 224,
+    crop_width: 224,
+    crop_height: 224,
     mean: IMAGENET_DEFAULT_MEAN,
     std: IMAGENET_DEFAULT_STD,
     interpolation: image::imageops::FilterType::CatmullRom,
+    resize_mode: ResizeMode::Exact,
+    fill: [0; 3],
+    layout: Layout::Chw,
+    channel_order: ChannelOrder::Rgb,
+    input_scale: 1.0 / 255.0,
+};
+
+/// YOLO-style letterboxing: 640x640 with gray padding.
+pub const LETTERBOX_DEFAULT_CONFIG: ImageConvert = ImageConvert {
+    channels: 3,
+    width: 640,
+    height: 640,
+    crop_width: 640,
+    crop_height: 640,
+    mean: [0.0; 3],
+    std: [1.0; 3],
+    interpolation: image::imageops::FilterType::Triangle,
+    resize_mode: ResizeMode::Letterbox { stride: None },
+    fill: [114; 3],
+    layout: Layout::Chw,
+    channel_order: ChannelOrder::Rgb,
+    input_scale: 1.0 / 255.0,
 };
 
+/// How the input image is fitted into the `width` x `height` resize box
+/// before the central crop is taken.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Hash)]
+pub enum ResizeMode {
+    /// Stretch to exactly `width` x `height`, ignoring the aspect ratio.
+    #[default]
+    Exact,
+    /// Scale the shorter side to `min(width, height)`, like torchvision's `Resize(int)`.
+    ShortestEdge,
+    /// Scale the longer side to `max(width, height)`.
+    LongestEdge,
+    /// Scale to fit inside the box (rounding like timm's `ResizeKeepRatio`);
+    /// the crop is padded where the image is smaller.
+    Fit,
+    /// Scale to cover the box (rounding like timm's `ResizeKeepRatio`); the
+    /// crop cuts off what overflows.
+    Fill,
+    /// Scale to fit inside the box (rounding like ultralytics) and pad the
+    /// crop with `fill`. With a `stride`, padding is only added up to the next
+    /// multiple of the stride, so the tensor size depends on the image.
+    Letterbox { stride: Option<u32> },
+}
+
+/// Memory layout of the produced tensors.
+///
+/// `tensorize` returns the 3-D form and `tensorize_batch` the 4-D form, so
+/// `Chw`/`Nchw` and `Hwc`/`Nhwc` only differ in which one they name.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Hash)]
+pub enum Layout {
+    /// Planar channels, as PyTorch models expect.
+    #[default]
+    Chw,
+    /// Interleaved channels, as TFLite and Keras models expect.
+    Hwc,
+    Nchw,
+    Nhwc,
+}
+
+impl Layout {
+    pub fn channels_last(self) -> bool {
+        matches!(self, Layout::Hwc | Layout::Nhwc)
+    }
+}
+
+/// Order of the color channels in the produced tensors.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Hash)]
+pub enum ChannelOrder {
+    #[default]
+    Rgb,
+    /// Caffe and OpenCV trained models, alpha stays last.
+    Bgr,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
 pub struct ImageConvert {
     //pub batches: u16,
     pub channels: u8,
     pub width: u32,
     pub height: u32,
-    pub crop: u16, //central crop of the image
+    pub crop_width: u32, //central crop of the image
+    pub crop_height: u32,
     pub mean: [f32; 3],
     pub std: [f32; 3],
     pub interpolation: image::imageops::FilterType,
+    pub resize_mode: ResizeMode,
+    pub fill: [u8; 3], //RGB color of the padding, alpha is 0
+    pub layout: Layout,
+    pub channel_order: ChannelOrder, //mean and std are given in this order
+    pub input_scale: f32,            //applied to 0..255 samples before normalization
+}
+
+/// Builds an [`ImageConvert`] and validates it in [`build`](Self::build).
+///
+/// Without an explicit crop the tensor covers the whole resized image.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct ImageConvertBuilder {
+    channels: Option<u8>,
+    resize: Option<(u32, u32)>,
+    crop: Option<(u32, u32)>,
+    mean: Option<[f32; 3]>,
+    std: Option<[f32; 3]>,
+    interpolation: Option<image::imageops::FilterType>,
+    resize_mode: Option<ResizeMode>,
+    fill: Option<[u8; 3]>,
+    layout: Option<Layout>,
+    channel_order: Option<ChannelOrder>,
+    input_scale: Option<f32>,
+}
+
+impl ImageConvertBuilder {
+    pub fn channels(mut self, channels: u8) -> Self {
+        self.channels = Some(channels);
+        self
+    }
+
+    pub fn resize(mut self, width: u32, height: u32) -> Self {
+        self.resize = Some((width, height));
+        self
+    }
+
+    pub fn crop(mut self, width: u32, height: u32) -> Self {
+        self.crop = Some((width, height));
+        self
+    }
+
+    pub fn mean(mut self, mean: [f32; 3]) -> Self {
+        self.mean = Some(mean);
+        self
+    }
+
+    pub fn std(mut self, std: [f32; 3]) -> Self {
+        self.std = Some(std);
+        self
+    }
+
+    pub fn interpolation(mut self, interpolation: image::imageops::FilterType) -> Self {
+        self.interpolation = Some(interpolation);
+        self
+    }
+
+    pub fn resize_mode(mut self, resize_mode: ResizeMode) -> Self {
+        self.resize_mode = Some(resize_mode);
+        self
+    }
+
+    pub fn fill(mut self, fill: [u8; 3]) -> Self {
+        self.fill = Some(fill);
+        self
+    }
+
+    pub fn layout(mut self, layout: Layout) -> Self {
+        self.layout = Some(layout);
+        self
+    }
+
+    pub fn channel_order(mut self, channel_order: ChannelOrder) -> Self {
+        self.channel_order = Some(channel_order);
+        self
+    }
+
+    /// Factor applied to the 0..255 samples before mean/std, `1.0 / 255.0` by default.
+    pub fn input_scale(mut self, input_scale: f32) -> Self {
+        self.input_scale = Some(input_scale);
+        self
+    }
+
+    /// Fills unset options from [`IMAGENET_DEFAULT_CONFIG`] and validates the result.
+    pub fn build(self) -> Result<ImageConvert> {
+        let (width, height) = self
+            .resize
+            .ok_or_else(|| TensorizeError::InvalidConfig("resize size must be set".to_string()))?;
+        let (crop_width, crop_height) = self.crop.unwrap_or((width, height));
+        let conv = ImageConvert {
+            channels: self.channels.unwrap_or(IMAGENET_DEFAULT_CONFIG.channels),
+            width,
+            height,
+            crop_width,
+            crop_height,
+            mean: self.mean.unwrap_or(IMAGENET_DEFAULT_CONFIG.mean),
+            std: self.std.unwrap_or(IMAGENET_DEFAULT_CONFIG.std),
+            interpolation: self
+                .interpolation
+                .unwrap_or(IMAGENET_DEFAULT_CONFIG.interpolation),
+            resize_mode: self.resize_mode.unwrap_or_default(),
+            fill: self.fill.unwrap_or(IMAGENET_DEFAULT_CONFIG.fill),
+            layout: self.layout.unwrap_or_default(),
+            channel_order: self.channel_order.unwrap_or_default(),
+            input_scale: self
+                .input_scale
+                .unwrap_or(IMAGENET_DEFAULT_CONFIG.input_scale),
+        };
+        conv.validate()?;
+        Ok(conv)
+    }
 }
 
 pub struct CpuTensorizer {
@@ -43,80 +242,553 @@ pub struct CpuTensorizer {
 impl Tensorizer for CpuTensorizer {
     type BuildType = CpuTensorizer;
 
-    async fn new(config: crate::cpu_tensor::ImageConvert) -> anyhow::Result<Self::BuildType> {
+    async fn new(config: crate::cpu_tensor::ImageConvert) -> Result<Self::BuildType> {
+        config.validate()?;
         Ok(CpuTensorizer { conv: config })
     }
 
-    async fn tensorize(&self, image: &DynamicImage) -> anyhow::Result<ndarray::Array3<f32>> {
-        self.conv.ort_value3(image)
+    fn config(&self) -> &ImageConvert {
+        &self.conv
+    }
+
+    async fn tensorize_as<T: TensorElement>(
+        &self,
+        image: &DynamicImage,
+    ) -> Result<ndarray::Array3<T>> {
+        Ok(self.conv.ort_value3(image, T::from_f32)?.0)
     }
 
-    async fn tensorize_batch(&self, image: &DynamicImage) -> anyhow::Result<ndarray::Array4<f32>> {
-        self.conv.ort_value(image)
+    async fn tensorize_quantized<Q: QuantElement>(
+        &self,
+        image: &DynamicImage,
+        params: QuantParams,
+    ) -> Result<ndarray::Array3<Q>> {
+        params.validate::<Q>()?;
+        Ok(self
+            .conv
+            .ort_value3(image, |value| params.quantize(value))?
+            .0)
+    }
+
+    async fn tensorize_letterbox(
+        &self,
+        image: &DynamicImage,
+    ) -> Result<(ndarray::Array3<f32>, LetterboxInfo)> {
+        self.conv.ort_value3(image, f32::from_f32)
+    }
+
+    async fn tensorize_batch_as<T: TensorElement>(
+        &self,
+        images: &[DynamicImage],
+    ) -> Result<ndarray::Array4<T>> {
+        self.conv.ort_value(images)
     }
 }
 
 impl ImageConvert {
+    pub fn builder() -> ImageConvertBuilder {
+        ImageConvertBuilder::default()
+    }
+
+    /// Checks that the configuration describes a tensor that can be produced.
+    pub fn validate(&self) -> Result<()> {
+        if !matches!(self.channels, 1 | 3 | 4) {
+            return Err(TensorizeError::InvalidConfig(format!(
+                "unsupported channel count {}, expected 1, 3 or 4",
+                self.channels
+            )));
+        }
+        if self.width == 0 || self.height == 0 {
+            return Err(TensorizeError::InvalidConfig(format!(
+                "resize size {}x{} is empty",
+                self.width, self.height
+            )));
+        }
+        if let ResizeMode::Letterbox { stride: Some(0) } = self.resize_mode {
+            return Err(TensorizeError::InvalidConfig(
+                "letterbox stride must be non-zero".to_string(),
+            ));
+        }
+        if self.crop_width == 0 || self.crop_height == 0 {
+            return Err(TensorizeError::InvalidConfig(format!(
+                "crop size {}x{} is empty",
+                self.crop_width, self.crop_height
+            )));
+        }
+        if self.crop_width > self.width || self.crop_height > self.height {
+            return Err(TensorizeError::InvalidConfig(format!(
+                "crop {}x{} is larger than the resized image {}x{}",
+                self.crop_width, self.crop_height, self.width, self.height
+            )));
+        }
+        if !(self.input_scale.is_finite() && self.input_scale > 0.0) {
+            return Err(TensorizeError::InvalidConfig(format!(
+                "input scale {} must be finite and positive",
+                self.input_scale
+            )));
+        }
+        if let Some(c) = (0..3).find(|&c| !self.mean[c].is_finite()) {
+            return Err(TensorizeError::InvalidConfig(format!(
+                "mean {} of channel {c} is not finite",
+                self.mean[c]
+            )));
+        }
+        if let Some(c) = (0..3).find(|&c| self.std[c] == 0.0 || !self.std[c].is_finite()) {
+            return Err(TensorizeError::InvalidConfig(format!(
+                "std {} of channel {c} must be finite and non-zero",
+                self.std[c]
+            )));
+        }
+        Ok(())
+    }
+
+    /// Size of an `input_width` x `input_height` image after resizing.
+    pub fn resized_size(&self, input_width: u32, input_height: u32) -> (u32, u32) {
+        let (w, h) = (input_width as u64, input_height as u64);
+        let (box_w, box_h) = (self.width as u64, self.height as u64);
+        // Target box and whether the width (rather than the height) sets the scale
+        let (target_w, target_h, by_width) = match self.resize_mode {
+            ResizeMode::Exact => return (self.width, self.height),
+            ResizeMode::ShortestEdge => {
+                let edge = box_w.min(box_h);
+                (edge, edge, w <= h)
+            }
+            ResizeMode::LongestEdge => {
+                let edge = box_w.max(box_h);
+                (edge, edge, w >= h)
+            }
+            ResizeMode::Fit | ResizeMode::Fill => {
+                // timm divides by the larger or smaller ratio of input to box
+                // size and rounds both sides half to even
+                let ratio = (w as f64 / box_w as f64, h as f64 / box_h as f64);
+                let ratio = if self.resize_mode == ResizeMode::Fit {
+                    ratio.0.max(ratio.1)
+                } else {
+                    ratio.0.min(ratio.1)
+                };
+                let size = |edge: u64| ((edge as f64 / ratio).round_ties_even() as u32).max(1);
+                return (size(w), size(h));
+            }
+            ResizeMode::Letterbox { .. } => {
+                // ultralytics rounds both sides of the scaled image
+                let scale = (box_w as f64 / w as f64).min(box_h as f64 / h as f64);
+                let size = |edge: u64| ((edge as f64 * scale).round() as u32).max(1);
+                return (size(w), size(h));
+            }
+        };
+        // The other side is truncated like torchvision, but never collapses to zero
+        if by_width {
+            (target_w as u32, (h * target_w / w).max(1) as u32)
+        } else {
+            ((w * target_h / h).max(1) as u32, target_h as u32)
+        }
+    }
+
+    /// Placement of an `input_width` x `input_height` image in its tensor.
+    pub fn letterbox_info(&self, input_width: u32, input_height: u32) -> LetterboxInfo {
+        let (resized_width, resized_height) = self.resized_size(input_width, input_height);
+        let (width, height) = match self.resize_mode {
+            // Only pad up to the next multiple of the stride
+            ResizeMode::Letterbox {
+                stride: Some(stride),
+            } => (
+                self.crop_width - (self.crop_width.saturating_sub(resized_width) / stride) * stride,
+                self.crop_height
+                    - (self.crop_height.saturating_sub(resized_height) / stride) * stride,
+            ),
+            _ => (self.crop_width, self.crop_height),
+        };
+        // Center the resized image, the odd pixel of padding or crop goes right/bottom
+        let offset = |resized: u32, size: u32| {
+            if resized >= size {
+                -(((resized - size) / 2) as i32)
+            } else {
+                ((size - resized) / 2) as i32
+            }
+        };
+        LetterboxInfo {
+            input_width,
+            input_height,
+            resized_width,
+            resized_height,
+            width,
+            height,
+            pad_x: offset(resized_width, width),
+            pad_y: offset(resized_height, height),
+        }
+    }
+
+    /// Shape of the tensor for one `width` x `height` image in the configured layout.
+    pub fn tensor_shape(&self, width: u32, height: u32) -> [usize; 3] {
+        let (c, h, w) = (self.channels as usize, height as usize, width as usize);
+        if self.layout.channels_last() {
+            [h, w, c]
+        } else {
+            [c, h, w]
+        }
+    }
+
     //#[cfg(feature = "ort")]
-    fn ort_value(&self, image: &DynamicImage) -> anyhow::Result<Array4<f32>> {
-        let normalized_data = self.create_data(image);
-        let tensor_shape: [usize; 4] = [
-            1,
-            self.channels as usize,
-            self.crop as usize,
-            self.crop as usize,
-        ];
+    fn ort_value<T: TensorElement>(&self, images: &[DynamicImage]) -> Result<Array4<T>> {
+        if images.is_empty() {
+            return Err(TensorizeError::EmptyBatch);
+        }
+        let mut normalized_data = Vec::new();
+        let mut size = None;
+        for image in images {
+            let (data, info) = self.create_data(image, T::from_f32)?;
+            if *size.get_or_insert((info.width, info.height)) != (info.width, info.height) {
+                return Err(TensorizeError::InvalidConfig(
+                    "images in a batch produce tensors of different sizes".to_string(),
+                ));
+            }
+            normalized_data.extend(data);
+        }
+        let (width, height) = size.unwrap();
+        let [d0, d1, d2] = self.tensor_shape(width, height);
+        let tensor_shape: [usize; 4] = [images.len(), d0, d1, d2];
         // let tensor_args = (tensor_shape, normalized_data);
-        let input_array =
-            ndarray::Array4::<f32>::from_shape_vec(Dim(tensor_shape), normalized_data)?; //ort::value::Tensor::from_array(tensor_args)?;
+        let input_array = ndarray::Array4::<T>::from_shape_vec(Dim(tensor_shape), normalized_data)?; //ort::value::Tensor::from_array(tensor_args)?;
         Ok(input_array)
     }
 
-    fn ort_value3(&self, image: &DynamicImage) -> anyhow::Result<Array3<f32>> {
-        let normalized_data = self.create_data(image);
-        let tensor_shape: [usize; 3] = [
-            self.channels as usize,
-            self.crop as usize,
-            self.crop as usize,
-        ];
+    fn ort_value3<T: Copy + Default>(
+        &self,
+        image: &DynamicImage,
+        convert: impl Fn(f32) -> T,
+    ) -> Result<(Array3<T>, LetterboxInfo)> {
+        let (normalized_data, info) = self.create_data(image, convert)?;
+        let tensor_shape: [usize; 3] = self.tensor_shape(info.width, info.height);
         // let tensor_args = (tensor_shape, normalized_data);
-        let input_array =
-            ndarray::Array3::<f32>::from_shape_vec(Dim(tensor_shape), normalized_data)?; //ort::value::Tensor::from_array(tensor_args)?;
-        Ok(input_array)
+        let input_array = ndarray::Array3::<T>::from_shape_vec(Dim(tensor_shape), normalized_data)?; //ort::value::Tensor::from_array(tensor_args)?;
+        Ok((input_array, info))
     }
 
-    fn create_data(&self, image: &DynamicImage) -> Vec<f32> {
-        let resized = image.resize_exact(self.width, self.height, self.interpolation);
+    /// Produces the tensor data of `image`, passing every normalized value
+    /// through `convert` as it is written.
+    fn create_data<T: Copy + Default>(
+        &self,
+        image: &DynamicImage,
+        convert: impl Fn(f32) -> T,
+    ) -> Result<(Vec<T>, LetterboxInfo)> {
+        self.validate()?;
+        if image.width() == 0 || image.height() == 0 {
+            return Err(TensorizeError::EmptyImage);
+        }
+        let info = self.letterbox_info(image.width(), image.height());
+        let resized =
+            image.resize_exact(info.resized_width, info.resized_height, self.interpolation);
 
-        // Central crop to 224 x 224
-        let (width, height) = (resized.width(), resized.height());
-        let crop_x = (width - 224) / 2;
-        let crop_y = (height - 224) / 2;
-        let cropped = resized.crop_imm(crop_x, crop_y, 224, 224);
+        // Central crop to the tensor size, padded where the resized image is smaller
+        let cropped = if info.pad_x <= 0
+            && info.pad_y <= 0
+            && info.resized_width >= info.width
+            && info.resized_height >= info.height
+        {
+            resized.crop_imm(
+                (-info.pad_x) as u32,
+                (-info.pad_y) as u32,
+                info.width,
+                info.height,
+            )
+        } else {
+            let [r, g, b] = self.fill;
+            let mut canvas = RgbaImage::from_pixel(info.width, info.height, Rgba([r, g, b, 0]));
+            image::imageops::replace(
+                &mut canvas,
+                &resized.to_rgba8(),
+                info.pad_x as i64,
+                info.pad_y as i64,
+            );
+            DynamicImage::ImageRgba8(canvas)
+        };
 
-        // Convert to f32 array and rescale to [0.0, 1.0]
-        let cropped_rgb = cropped.to_rgb8();
-        let (width, height) = (cropped.width(), cropped.height());
+        // Interleaved 8-bit samples with the configured number of channels
+        let samples = match self.channels {
+            1 => cropped.to_luma8().into_raw(),
+            3 => cropped.to_rgb8().into_raw(),
+            _ => cropped.to_rgba8().into_raw(),
+        };
+        let channels = self.channels as usize;
+        let plane = (info.width * info.height) as usize;
 
-        let mut data = Vec::new();
+        // Rescale by input_scale, reorder, reshape into [C, H, W] or [H, W, C] and normalize.
+        // Grayscale uses the first mean/std entry, alpha is only rescaled.
+        let mean = self.mean;
+        let std = self.std;
+        let channels_last = self.layout.channels_last();
+        let bgr = channels >= 3 && self.channel_order == ChannelOrder::Bgr;
 
-        for pixel in cropped_rgb.pixels() {
-            data.push(pixel[0] as f32 / 255.0); // R
-            data.push(pixel[1] as f32 / 255.0); // G
-            data.push(pixel[2] as f32 / 255.0); // B
+        let mut normalized_data = vec![T::default(); samples.len()];
+        for (i, pixel) in samples.chunks_exact(channels).enumerate() {
+            for c in 0..channels {
+                let source = if bgr && c < 3 { 2 - c } else { c };
+                let value = pixel[source] as f32 * self.input_scale;
+                let index = if channels_last {
+                    i * channels + c
+                } else {
+                    c * plane + i
+                };
+                normalized_data[index] = convert(if c < 3 {
+                    (value - mean[c]) / std[c]
+                } else {
+                    value
+                });
+            }
         }
+        Ok((normalized_data, info))
+    }
 
-        // Reshape into [3, 224, 224] and normalize
-        let mean = self.mean;
-        let std = self.std;
+    /// Turns a tensor produced with this config back into an image, undoing
+    /// the normalization, input scale, channel order and layout.
+    ///
+    /// Values are rounded and clamped to `0..=255`, padding shows up as the
+    /// fill color.
+    pub fn detensorize(&self, tensor: &Array3<f32>) -> Result<DynamicImage> {
+        let channels = self.channels as usize;
+        let channels_last = self.layout.channels_last();
+        let (height, width, tensor_channels) = match tensor.dim() {
+            (h, w, c) if channels_last => (h, w, c),
+            (c, h, w) => (h, w, c),
+        };
+        if tensor_channels != channels {
+            return Err(TensorizeError::InvalidConfig(format!(
+                "tensor has {tensor_channels} channels, config expects {channels}"
+            )));
+        }
+        let bgr = channels >= 3 && self.channel_order == ChannelOrder::Bgr;
 
-        let mut normalized_data = vec![0.0; data.len()];
-        for c in 0..3 {
-            for i in 0..(width * height) as usize {
-                normalized_data[c * (width * height) as usize + i] =
-                    (data[i * 3 + c] - mean[c]) / std[c];
+        let mut samples = vec![0u8; height * width * channels];
+        for y in 0..height {
+            for x in 0..width {
+                for c in 0..channels {
+                    let index = if channels_last { [y, x, c] } else { [c, y, x] };
+                    let value = if c < 3 {
+                        tensor[index] * self.std[c] + self.mean[c]
+                    } else {
+                        tensor[index]
+                    };
+                    let target = if bgr && c < 3 { 2 - c } else { c };
+                    samples[(y * width + x) * channels + target] =
+                        (value / self.input_scale).round().clamp(0.0, 255.0) as u8;
+                }
             }
         }
-        normalized_data
+
+        let (width, height) = (width as u32, height as u32);
+        let image = match self.channels {
+            1 => GrayImage::from_raw(width, height, samples).map(DynamicImage::ImageLuma8),
+            3 => RgbImage::from_raw(width, height, samples).map(DynamicImage::ImageRgb8),
+            _ => RgbaImage::from_raw(width, height, samples).map(DynamicImage::ImageRgba8),
+        };
+        Ok(image.expect("sample buffer matches the tensor size"))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// An image whose samples differ per pixel and channel.
+    fn gradient(width: u32, height: u32) -> RgbaImage {
+        RgbaImage::from_fn(width, height, |x, y| {
+            Rgba([
+                (x * 7) as u8,
+                (y * 11) as u8,
+                (x * y) as u8,
+                (200 + x) as u8,
+            ])
+        })
+    }
+
+    async fn round_trip(conv: ImageConvert, image: &DynamicImage) -> DynamicImage {
+        let tensor = CpuTensorizer::new(conv)
+            .await
+            .unwrap()
+            .tensorize(image)
+            .await
+            .unwrap();
+        conv.detensorize(&tensor).unwrap()
+    }
+
+    #[tokio::test]
+    async fn detensorize_undoes_chw_rgb() {
+        let image = DynamicImage::ImageRgb8(DynamicImage::ImageRgba8(gradient(24, 16)).to_rgb8());
+        let conv = ImageConvert::builder().resize(24, 16).build().unwrap();
+        assert_eq!(round_trip(conv, &image).await, image);
+    }
+
+    #[tokio::test]
+    async fn detensorize_undoes_hwc_bgra() {
+        let image = DynamicImage::ImageRgba8(gradient(24, 16));
+        let conv = ImageConvert::builder()
+            .resize(24, 16)
+            .channels(4)
+            .layout(Layout::Hwc)
+            .channel_order(ChannelOrder::Bgr)
+            .mean([0.406, 0.456, 0.485])
+            .std([0.225, 0.224, 0.229])
+            .build()
+            .unwrap();
+        assert_eq!(round_trip(conv, &image).await, image);
+    }
+
+    #[tokio::test]
+    async fn detensorize_shows_letterbox_padding() {
+        let image = DynamicImage::ImageRgb8(DynamicImage::ImageRgba8(gradient(16, 8)).to_rgb8());
+        let conv = ImageConvert::builder()
+            .resize(16, 16)
+            .resize_mode(ResizeMode::Letterbox { stride: None })
+            .fill([114; 3])
+            .mean([0.0; 3])
+            .std([1.0; 3])
+            .build()
+            .unwrap();
+        let restored = round_trip(conv, &image).await.to_rgb8();
+        // The image keeps its size and sits between four rows of fill color
+        assert_eq!(restored.dimensions(), (16, 16));
+        for (x, y, pixel) in restored.enumerate_pixels() {
+            let expected = match y {
+                4..12 => *image.to_rgb8().get_pixel(x, y - 4),
+                _ => image::Rgb([114; 3]),
+            };
+            assert_eq!(*pixel, expected, "({x}, {y})");
+        }
+    }
+
+    #[test]
+    fn detensorize_rejects_other_channel_counts() {
+        let conv = ImageConvert::builder().resize(4, 4).build().unwrap();
+        let tensor = Array3::<f32>::zeros((4, 4, 4));
+        assert!(matches!(
+            conv.detensorize(&tensor),
+            Err(TensorizeError::InvalidConfig(_))
+        ));
+    }
+
+    #[tokio::test]
+    async fn crops_non_square_from_the_center() {
+        let image = DynamicImage::ImageRgba8(gradient(40, 30));
+        let conv = ImageConvert::builder()
+            .resize(40, 30)
+            .crop(24, 10)
+            .mean([0.0; 3])
+            .std([1.0; 3])
+            .input_scale(1.0)
+            .build()
+            .unwrap();
+        let tensor: Array3<f32> = CpuTensorizer::new(conv)
+            .await
+            .unwrap()
+            .tensorize(&image)
+            .await
+            .unwrap();
+        assert_eq!(tensor.dim(), (3, 10, 24));
+        // The crop starts at ((40 - 24) / 2, (30 - 10) / 2)
+        assert_eq!(tensor[[0, 0, 0]], (8 * 7) as f32);
+        assert_eq!(tensor[[1, 0, 0]], (10 * 11) as f32);
+    }
+
+    #[tokio::test]
+    async fn writes_one_or_four_channels() {
+        let image = DynamicImage::ImageRgba8(gradient(8, 6));
+        let gray = ImageConvert::builder()
+            .resize(8, 6)
+            .channels(1)
+            .mean([0.0; 3])
+            .std([1.0; 3])
+            .input_scale(1.0)
+            .build()
+            .unwrap();
+        let tensor: Array3<f32> = CpuTensorizer::new(gray)
+            .await
+            .unwrap()
+            .tensorize(&image)
+            .await
+            .unwrap();
+        assert_eq!(tensor.dim(), (1, 6, 8));
+        assert_eq!(
+            tensor[[0, 5, 7]],
+            image.to_luma8().get_pixel(7, 5)[0] as f32
+        );
+
+        let rgba = ImageConvert {
+            channels: 4,
+            ..gray
+        };
+        let tensor: Array3<f32> = CpuTensorizer::new(rgba)
+            .await
+            .unwrap()
+            .tensorize(&image)
+            .await
+            .unwrap();
+        assert_eq!(tensor.dim(), (4, 6, 8));
+        // Alpha is only rescaled
+        assert_eq!(tensor[[3, 2, 5]], 205.0);
+    }
+
+    #[tokio::test]
+    async fn invalid_configs_fail_instead_of_panicking() {
+        let image = DynamicImage::ImageRgba8(gradient(8, 8));
+        let valid = ImageConvert::builder().resize(8, 8).build().unwrap();
+        for conv in [
+            ImageConvert {
+                channels: 2,
+                ..valid
+            },
+            ImageConvert {
+                crop_width: 16,
+                ..valid
+            },
+            ImageConvert { width: 0, ..valid },
+        ] {
+            assert!(matches!(
+                CpuTensorizer::new(conv).await,
+                Err(TensorizeError::InvalidConfig(_))
+            ));
+            let tensor: Result<Array3<f32>> = CpuTensorizer { conv }.tensorize(&image).await;
+            assert!(matches!(tensor, Err(TensorizeError::InvalidConfig(_))));
+        }
+    }
+
+    #[test]
+    fn build_rejects_invalid_options() {
+        let base = ImageConvert::builder().resize(256, 256).crop(224, 224);
+        let invalid = [
+            ("missing resize", ImageConvert::builder().crop(224, 224)),
+            ("channel count", base.channels(2)),
+            ("crop larger than resize", base.crop(320, 224)),
+            ("zero std", base.std([0.229, 0.0, 0.225])),
+            ("non-finite std", base.std([f32::NAN, 0.224, 0.225])),
+            ("non-finite mean", base.mean([0.485, f32::INFINITY, 0.406])),
+            (
+                "zero stride",
+                base.resize_mode(ResizeMode::Letterbox { stride: Some(0) }),
+            ),
+            ("zero input scale", base.input_scale(0.0)),
+            ("empty resize", base.resize(0, 256)),
+        ];
+        for (name, builder) in invalid {
+            assert!(
+                matches!(builder.build(), Err(TensorizeError::InvalidConfig(_))),
+                "{name}"
+            );
+        }
+    }
+
+    #[test]
+    fn build_fills_defaults() {
+        let conv = ImageConvert::builder().resize(256, 256).build().unwrap();
+        // Without a crop the tensor covers the resized image
+        assert_eq!(
+            conv,
+            ImageConvert {
+                width: 256,
+                height: 256,
+                crop_width: 256,
+                crop_height: 256,
+                ..IMAGENET_DEFAULT_CONFIG
+            }
+        );
     }
 }
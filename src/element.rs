@@ -0,0 +1,102 @@
+//! Tensor element types.
+
+use half::{bf16, f16};
+
+use crate::error::{Result, TensorizeError};
+
+/// A scalar type tensors can be produced in.
+///
+/// Values are converted once while the tensor is written, so requesting
+/// [`f16`] or [`bf16`] avoids a separate conversion pass over an `f32` tensor.
+pub trait TensorElement: Copy + Default + Send + Sync + 'static {
+    /// Whether the GPU backend should write half precision texels, halving
+    /// the readback size.
+    const GPU_HALF: bool = false;
+
+    fn from_f32(value: f32) -> Self;
+}
+
+impl TensorElement for f32 {
+    fn from_f32(value: f32) -> Self {
+        value
+    }
+}
+
+impl TensorElement for f16 {
+    const GPU_HALF: bool = true;
+
+    fn from_f32(value: f32) -> Self {
+        f16::from_f32(value)
+    }
+}
+
+impl TensorElement for bf16 {
+    fn from_f32(value: f32) -> Self {
+        bf16::from_f32(value)
+    }
+}
+
+/// Affine quantization of a model input, `q = round(x / scale) + zero_point`.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct QuantParams {
+    pub scale: f32,
+    pub zero_point: i32,
+}
+
+impl QuantParams {
+    pub fn new(scale: f32, zero_point: i32) -> Self {
+        QuantParams { scale, zero_point }
+    }
+
+    /// Checks that the scale is usable and the zero point fits into `Q`.
+    pub fn validate<Q: QuantElement>(&self) -> Result<()> {
+        if !(self.scale.is_finite() && self.scale > 0.0) {
+            return Err(TensorizeError::InvalidConfig(format!(
+                "quantization scale {} must be finite and positive",
+                self.scale
+            )));
+        }
+        if !(Q::MIN..=Q::MAX).contains(&self.zero_point) {
+            return Err(TensorizeError::InvalidConfig(format!(
+                "zero point {} is outside {}..={}",
+                self.zero_point,
+                Q::MIN,
+                Q::MAX
+            )));
+        }
+        Ok(())
+    }
+
+    /// Quantizes a normalized value, saturating at the range of `Q`.
+    pub fn quantize<Q: QuantElement>(&self, value: f32) -> Q {
+        let q = (value / self.scale).round() + self.zero_point as f32;
+        Q::from_i32((q as i32).clamp(Q::MIN, Q::MAX))
+    }
+}
+
+/// An integer type quantized tensors can be produced in.
+pub trait QuantElement: Copy + Default + Send + Sync + 'static {
+    const MIN: i32;
+    const MAX: i32;
+
+    /// Converts a value already clamped to `MIN..=MAX`.
+    fn from_i32(value: i32) -> Self;
+}
+
+impl QuantElement for u8 {
+    const MIN: i32 = u8::MIN as i32;
+    const MAX: i32 = u8::MAX as i32;
+
+    fn from_i32(value: i32) -> Self {
+        value as u8
+    }
+}
+
+impl QuantElement for i8 {
+    const MIN: i32 = i8::MIN as i32;
+    const MAX: i32 = i8::MAX as i32;
+
+    fn from_i32(value: i32) -> Self {
+        value as i8
+    }
+}
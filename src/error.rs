@@ -0,0 +1,35 @@
+use thiserror::Error;
+
+pub type Result<T> = std::result::Result<T, TensorizeError>;
+
+#[derive(Debug, Error)]
+pub enum TensorizeError {
+    #[error("no suitable GPU adapter found: {0}")]
+    NoAdapter(#[from] wgpu::RequestAdapterError),
+    #[error("no GPU adapter matches {0}")]
+    AdapterNotFound(String),
+    #[error("failed to request a GPU device: {0}")]
+    RequestDevice(#[from] wgpu::RequestDeviceError),
+    #[error("GPU device lost: {0}")]
+    DeviceLost(String),
+    #[error("GPU error: {0}")]
+    Gpu(String),
+    #[error("invalid config: {0}")]
+    InvalidConfig(String),
+    #[error("image {width}x{height} exceeds the maximum texture dimension {max}")]
+    ImageTooLarge { width: u32, height: u32, max: u32 },
+    #[error("image has no pixels")]
+    EmptyImage,
+    #[error("cannot tensorize an empty batch")]
+    EmptyBatch,
+    #[error("failed to map GPU buffer: {0}")]
+    BufferMap(#[from] wgpu::BufferAsyncError),
+    #[error(transparent)]
+    Shape(#[from] ndarray::ShapeError),
+    #[error(transparent)]
+    Image(#[from] image::ImageError),
+    #[error(transparent)]
+    Io(#[from] std::io::Error),
+    #[error("failed to parse preprocessing config: {0}")]
+    Json(#[from] serde_json::Error),
+}
@@ -0,0 +1,336 @@
+//! A wgpu device shared by the GPU components of the crate.
+
+use std::{
+    pin::pin,
+    sync::{Arc, Mutex, Weak},
+    task::{Context, Poll, Waker},
+};
+
+use bytemuck::Pod;
+use wgpu::{
+    Adapter, AdapterInfo, Backends, Buffer, BufferAsyncError, BufferSlice, Device, Instance,
+    PowerPreference, Queue,
+};
+
+use crate::{
+    error::{Result, TensorizeError},
+    poller::Poller,
+};
+
+/// Picks one adapter out of those the instance enumerates.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum AdapterSelector {
+    /// The first adapter whose name contains this string, ignoring case.
+    Name(String),
+    /// The adapter at this position in the enumeration order.
+    Index(usize),
+}
+
+/// How [`GpuContext`] chooses its adapter.
+#[derive(Clone, Debug)]
+pub struct GpuOptions {
+    /// Backends to consider, e.g. `Backends::VULKAN` or `Backends::GL`.
+    pub backends: Backends,
+    pub power_preference: PowerPreference,
+    /// Only use a software adapter such as llvmpipe or WARP.
+    pub force_fallback_adapter: bool,
+    /// Select a specific adapter instead of letting wgpu choose.
+    pub adapter: Option<AdapterSelector>,
+}
+
+impl Default for GpuOptions {
+    fn default() -> Self {
+        GpuOptions {
+            backends: Backends::all(),
+            power_preference: PowerPreference::default(),
+            force_fallback_adapter: false,
+            adapter: None,
+        }
+    }
+}
+
+impl GpuOptions {
+    pub fn backends(mut self, backends: Backends) -> Self {
+        self.backends = backends;
+        self
+    }
+
+    pub fn power_preference(mut self, power_preference: PowerPreference) -> Self {
+        self.power_preference = power_preference;
+        self
+    }
+
+    pub fn force_fallback_adapter(mut self, force_fallback_adapter: bool) -> Self {
+        self.force_fallback_adapter = force_fallback_adapter;
+        self
+    }
+
+    pub fn adapter(mut self, adapter: AdapterSelector) -> Self {
+        self.adapter = Some(adapter);
+        self
+    }
+}
+
+/// The instance, adapter, device and queue the GPU components run on.
+///
+/// Cloning is cheap and every clone refers to the same device, so one context
+/// can be passed to both [`GpuTensorizer`](crate::GpuTensorizer) and
+/// [`ImageResizer`](crate::ImageResizer).
+#[derive(Clone)]
+pub struct GpuContext {
+    instance: Option<Instance>,
+    adapter: Option<Adapter>,
+    device: Arc<Device>,
+    queue: Arc<Queue>,
+    shared: Arc<Shared>,
+}
+
+/// State of one device shared by every context on it.
+struct Shared {
+    /// Why the device was lost, once the lost callback has run.
+    lost: Mutex<Option<String>>,
+    /// Held while this crate's error scopes are pushed on the device.
+    scopes: Mutex<()>,
+    poller: Poller,
+}
+
+/// The contexts created by [`GpuContext::from_device`], so that every
+/// component given the same `Arc<Device>` shares one poll thread.
+type Registry = Vec<(Weak<Device>, Weak<Queue>, Weak<Shared>)>;
+static CONTEXTS: Mutex<Registry> = Mutex::new(Vec::new());
+
+impl GpuContext {
+    /// Requests the default adapter and a device on it.
+    pub async fn new() -> Result<Self> {
+        GpuContext::with_options(GpuOptions::default()).await
+    }
+
+    /// Requests the adapter described by `options` and a device on it.
+    pub async fn with_options(options: GpuOptions) -> Result<Self> {
+        let instance = wgpu::Instance::new(&wgpu::InstanceDescriptor {
+            backends: options.backends,
+            ..Default::default()
+        });
+        let adapter = match &options.adapter {
+            Some(selector) => select_adapter(&instance, &options, selector)?,
+            None => {
+                instance
+                    .request_adapter(&wgpu::RequestAdapterOptions {
+                        power_preference: options.power_preference,
+                        compatible_surface: None,
+                        force_fallback_adapter: options.force_fallback_adapter,
+                    })
+                    .await?
+            }
+        };
+        log::debug!("using GPU adapter {:?}", adapter.get_info());
+
+        let (device, queue) = adapter
+            .request_device(&wgpu::DeviceDescriptor {
+                label: None,
+                required_features: wgpu::Features::empty(),
+                required_limits: wgpu::Limits::default(),
+                memory_hints: wgpu::MemoryHints::Performance,
+                trace: wgpu::Trace::default(),
+            })
+            .await?;
+
+        let mut context = GpuContext::from_device(Arc::new(device), Arc::new(queue));
+        context.install_handlers();
+        context.instance = Some(instance);
+        context.adapter = Some(adapter);
+        Ok(context)
+    }
+
+    /// Wraps a device created elsewhere, e.g. one shared with other wgpu code.
+    ///
+    /// Calls with the same `Arc<Device>` return the same context, so they
+    /// share one thread polling the device. The device's lost callback and
+    /// uncaptured error handler are left alone unless
+    /// [`install_handlers`](Self::install_handlers) is called. Without them
+    /// a lost device fails with [`TensorizeError::BufferMap`] instead of
+    /// [`TensorizeError::DeviceLost`].
+    pub fn from_device(device: Arc<Device>, queue: Arc<Queue>) -> Self {
+        let mut contexts = CONTEXTS.lock().unwrap();
+        contexts
+            .retain(|(device, _, shared)| device.strong_count() > 0 && shared.strong_count() > 0);
+        let existing = contexts
+            .iter()
+            .filter(|(known, _, _)| known.as_ptr() == Arc::as_ptr(&device))
+            .find_map(|(_, queue, shared)| Some((queue.upgrade()?, shared.upgrade()?)));
+        let (queue, shared) = existing.unwrap_or_else(|| {
+            let shared = Arc::new(Shared {
+                lost: Mutex::new(None),
+                scopes: Mutex::new(()),
+                poller: Poller::new(Device::clone(&device)),
+            });
+            contexts.push((
+                Arc::downgrade(&device),
+                Arc::downgrade(&queue),
+                Arc::downgrade(&shared),
+            ));
+            (queue, shared)
+        });
+        GpuContext {
+            instance: None,
+            adapter: None,
+            device,
+            queue,
+            shared,
+        }
+    }
+
+    /// Sets the device lost callback, so later calls report
+    /// [`TensorizeError::DeviceLost`], and an uncaptured error handler that
+    /// logs instead of panicking like wgpu's default.
+    ///
+    /// wgpu keeps only one of each per device, so this replaces any the
+    /// device already had, including ones set by other code sharing it.
+    /// Contexts that request their own device install them on creation.
+    pub fn install_handlers(&self) {
+        self.device.set_device_lost_callback({
+            let shared = Arc::downgrade(&self.shared);
+            move |_reason, message| {
+                if let Some(shared) = shared.upgrade() {
+                    *shared.lost.lock().unwrap() = Some(message);
+                }
+            }
+        });
+        // Errors of this crate's own commands are captured in error scopes,
+        // so the rest is only logged
+        self.device
+            .on_uncaptured_error(Box::new(|err| log::error!("uncaptured GPU error: {err}")));
+    }
+
+    /// The instance, unless the context was created from an existing device.
+    pub fn instance(&self) -> Option<&Instance> {
+        self.instance.as_ref()
+    }
+
+    /// The adapter, unless the context was created from an existing device.
+    pub fn adapter(&self) -> Option<&Adapter> {
+        self.adapter.as_ref()
+    }
+
+    /// Describes the adapter, unless the context was created from an existing device.
+    pub fn adapter_info(&self) -> Option<AdapterInfo> {
+        self.adapter.as_ref().map(Adapter::get_info)
+    }
+
+    pub fn device(&self) -> &Arc<Device> {
+        &self.device
+    }
+
+    pub fn queue(&self) -> &Arc<Queue> {
+        &self.queue
+    }
+
+    /// Fails with [`TensorizeError::DeviceLost`] once the device is gone.
+    pub(crate) fn check_device(&self) -> Result<()> {
+        match self.shared.lost.lock().unwrap().clone() {
+            Some(message) => Err(TensorizeError::DeviceLost(message)),
+            None => Ok(()),
+        }
+    }
+
+    /// Like [`check_device`](Self::check_device), but first polls the device
+    /// so a pending lost callback has run.
+    pub(crate) fn detect_loss(&self) -> Result<()> {
+        if let Err(err) = self.device.poll(wgpu::PollType::Poll) {
+            log::debug!("polling for device loss failed: {err}");
+        }
+        self.check_device()
+    }
+
+    /// Creates a uniform buffer holding `value`.
+    ///
+    /// It is written through the queue rather than mapped at creation, which
+    /// panics if the device is lost meanwhile.
+    pub(crate) fn uniform_buffer<T: Pod>(&self, label: &str, value: &T) -> Buffer {
+        let buffer = self.device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some(label),
+            size: std::mem::size_of::<T>() as u64,
+            usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
+            mapped_at_creation: false,
+        });
+        self.queue
+            .write_buffer(&buffer, 0, bytemuck::bytes_of(value));
+        buffer
+    }
+
+    /// Runs `submit` inside validation and out-of-memory error scopes and
+    /// fails if it caused an error, as [`TensorizeError::DeviceLost`] if the
+    /// device is gone.
+    ///
+    /// wgpu keeps one scope stack per device, so calls through clones of
+    /// this context take turns.
+    pub(crate) fn scoped<R>(&self, submit: impl FnOnce() -> R) -> Result<R> {
+        let (result, validation, out_of_memory) = {
+            let _scopes = self.shared.scopes.lock().unwrap();
+            self.device.push_error_scope(wgpu::ErrorFilter::OutOfMemory);
+            self.device.push_error_scope(wgpu::ErrorFilter::Validation);
+            let result = submit();
+            let validation = self.device.pop_error_scope();
+            (result, validation, self.device.pop_error_scope())
+        };
+        match resolved(validation)
+            .flatten()
+            .or_else(|| resolved(out_of_memory).flatten())
+        {
+            Some(err) => {
+                self.detect_loss()?;
+                Err(TensorizeError::Gpu(err.to_string()))
+            }
+            None => Ok(result),
+        }
+    }
+
+    /// Maps `slice` for reading without blocking the async runtime.
+    pub(crate) async fn map_read(
+        &self,
+        slice: BufferSlice<'_>,
+    ) -> std::result::Result<(), BufferAsyncError> {
+        self.shared.poller.map_read(slice).await
+    }
+}
+
+/// The output of `future` if it is ready without waiting, which native wgpu
+/// guarantees for popped error scopes.
+fn resolved<F: Future>(future: F) -> Option<F::Output> {
+    match pin!(future).poll(&mut Context::from_waker(Waker::noop())) {
+        Poll::Ready(output) => Some(output),
+        Poll::Pending => None,
+    }
+}
+
+fn select_adapter(
+    instance: &Instance,
+    options: &GpuOptions,
+    selector: &AdapterSelector,
+) -> Result<Adapter> {
+    let mut adapters = instance.enumerate_adapters(options.backends);
+    if options.force_fallback_adapter {
+        adapters.retain(|adapter| adapter.get_info().device_type == wgpu::DeviceType::Cpu);
+    }
+    let index = match selector {
+        AdapterSelector::Name(name) => {
+            let name = name.to_lowercase();
+            adapters
+                .iter()
+                .position(|adapter| adapter.get_info().name.to_lowercase().contains(&name))
+        }
+        AdapterSelector::Index(index) => (*index < adapters.len()).then_some(*index),
+    };
+    match index {
+        Some(index) => Ok(adapters.swap_remove(index)),
+        None => {
+            let available: Vec<String> = adapters
+                .iter()
+                .map(|adapter| adapter.get_info().name)
+                .collect();
+            Err(TensorizeError::AdapterNotFound(format!(
+                "{selector:?}, available: {available:?}"
+            )))
+        }
+    }
+}
@@ -1,30 +1,65 @@
-use std::num::NonZeroU32;
+use std::{
+    collections::HashMap,
+    num::NonZeroU32,
+    sync::{Arc, Mutex, RwLock},
+    time::Duration,
+};
 
-use anyhow::Ok;
-use image::{DynamicImage, GenericImageView};
+use half::f16;
+use image::{DynamicImage, GenericImageView, imageops::FilterType};
 use ndarray::{Array3, Array4};
-use wgpu::{
-    BindGroupLayout, ComputePipeline, Device, Queue, ShaderModule, include_wgsl, util::DeviceExt,
+use wgpu::{BindGroupLayout, ComputePipeline, Device, Queue, ShaderModule, include_wgsl};
+
+use crate::{
+    cpu_tensor::{ChannelOrder, ImageConvert},
+    element::{QuantElement, QuantParams, TensorElement},
+    error::{Result, TensorizeError},
+    gpu_context::{GpuContext, GpuOptions},
+    letterbox::LetterboxInfo,
+    tensorizer_trait::Tensorizer,
 };
 
-use crate::tensorizer_trait::Tensorizer;
-
 impl Tensorizer for GpuTensorizer {
     type BuildType = GpuTensorizer;
 
-    async fn new(config: crate::cpu_tensor::ImageConvert) -> anyhow::Result<Self::BuildType> {
-        GpuTensorizer::new(config.width, config.height, None, config.mean, config.std).await
+    async fn new(config: ImageConvert) -> Result<Self::BuildType> {
+        GpuTensorizer::new(config).await
+    }
+
+    fn config(&self) -> &ImageConvert {
+        &self.conv
     }
 
-    async fn tensorize(&self, image: &DynamicImage) -> anyhow::Result<ndarray::Array3<f32>> {
+    async fn tensorize_as<T: TensorElement>(
+        &self,
+        image: &DynamicImage,
+    ) -> Result<ndarray::Array3<T>> {
         self.tensorize(image).await
     }
 
-    async fn tensorize_batch(&self, image: &DynamicImage) -> anyhow::Result<ndarray::Array4<f32>> {
-        self.tensorize_with_batch(image).await
+    async fn tensorize_batch_as<T: TensorElement>(
+        &self,
+        images: &[DynamicImage],
+    ) -> Result<ndarray::Array4<T>> {
+        self.tensorize_with_batch(images, Output::float(T::GPU_HALF), T::from_f32)
+            .await
     }
-}
 
+    async fn tensorize_quantized<Q: QuantElement>(
+        &self,
+        image: &DynamicImage,
+        params: QuantParams,
+    ) -> Result<ndarray::Array3<Q>> {
+        // Quantized by the shader, only the 8-bit values are read back
+        let output = Output::quantized::<Q>(params)?;
+        let a4 = self
+            .tensorize_with_batch(std::slice::from_ref(image), output, |value| {
+                Q::from_i32(value as i32)
+            })
+            .await?;
+        Ok(a4.index_axis_move(ndarray::Axis(0), 0))
+    }
+}
 #[repr(C)]
 #[derive(Copy, Clone, bytemuck::Pod, bytemuck::Zeroable)]
 struct TensorParams {
@@ -32,55 +67,327 @@ struct TensorParams {
     input_height: u32,
     output_width: u32,
     output_height: u32,
+    resize_width: u32,
+    resize_height: u32,
+    crop_x: i32,
+    crop_y: i32,
+    channels: u32,
+    bgr: u32,
+    scale: f32,
+    channels_last: u32,
+    quant_scale: f32,
+    zero_point: i32,
+    quant_min: i32,
+    quant_max: i32,
+    interpolation: u32,
+    _padding: [u32; 3],
     mean: [f32; 4],
     avg: [f32; 4],
+    fill: [f32; 4],
+}
+
+/// Element type of a [`GpuTensor`].
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Hash)]
+pub enum DType {
+    #[default]
+    F32,
+    /// IEEE half precision, two values packed per `u32`.
+    F16,
 }
 
+/// A tensor that stays on the GPU, laid out like the `ndarray` tensors.
+#[derive(Debug)]
+pub struct GpuTensor {
+    pub buffer: wgpu::Buffer,
+    /// Batch size followed by the configured layout.
+    pub shape: [usize; 4],
+    pub dtype: DType,
+}
+
+/// How a [`GpuTensorizer`] replaces a lost device.
+///
+/// A call that finds the device lost requests a new one up to `max_attempts`
+/// times, waiting `backoff` before the first attempt and twice as long before
+/// each further one, and then runs once more on the new device.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct RecoveryPolicy {
+    /// Zero reports every loss as [`TensorizeError::DeviceLost`].
+    pub max_attempts: u32,
+    pub backoff: Duration,
+}
+
+impl Default for RecoveryPolicy {
+    fn default() -> Self {
+        RecoveryPolicy {
+            max_attempts: 3,
+            backoff: Duration::from_millis(100),
+        }
+    }
+}
+
+/// Tensorizes images with a compute shader.
+///
+/// The shader implements every [`FilterType`], but unlike `image` it does
+/// not widen the filter when downscaling: it samples at most the 6x6 pixels
+/// around each output pixel, so downscaling by a large factor aliases more
+/// than on the CPU.
+///
+/// If the device is lost, for example after a driver reset, the device,
+/// pipeline and pooled resources are created again following the
+/// [`RecoveryPolicy`]. This needs the tensorizer to have requested its own
+/// device; one created with `with_context` or `from_device` reports the loss.
 pub struct GpuTensorizer {
-    device: Device,
-    queue: Queue,
+    state: RwLock<Arc<DeviceState>>,
+    conv: ImageConvert,
+    /// How to request a new device, unless the device came from elsewhere.
+    options: Option<GpuOptions>,
+    recovery: RecoveryPolicy,
+    /// Held while replacing the device so concurrent calls replace it once.
+    recovering: tokio::sync::Mutex<()>,
+}
+
+/// Everything tied to one device, replaced as a whole when it is lost.
+struct DeviceState {
+    context: GpuContext,
     compute_pipeline: ComputePipeline,
+    /// Writes packed f16 values instead of f32.
+    packed_pipeline: ComputePipeline,
+    /// Writes packed 8-bit quantized values instead of f32.
+    quantized_pipeline: ComputePipeline,
     bind_group_layout: BindGroupLayout,
+    conv: ImageConvert,
+    /// `conv.interpolation` as passed to the shader.
+    interpolation: u32,
+    slots: Mutex<HashMap<SlotKey, Vec<ImageSlot>>>,
+    staging: Mutex<Vec<wgpu::Buffer>>,
+}
+
+/// Number of distinct input sizes whose resources are kept between calls.
+const MAX_POOLED_SIZES: usize = 32;
+/// Number of idle staging buffers kept between calls.
+const MAX_POOLED_STAGING: usize = 4;
+
+/// Input width, input height and how the output is written.
+type SlotKey = (u32, u32, Output);
+
+/// How the shader writes tensor values.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+enum Output {
+    F32,
+    /// f16 values packed in pairs.
+    F16,
+    /// 8-bit values quantized with `scale` (as bits) and `zero_point`,
+    /// clamped to `min..=max` and packed in quads.
+    Quant8 {
+        scale: u32,
+        zero_point: i32,
+        min: i32,
+        max: i32,
+    },
+}
+
+impl Output {
+    fn float(half: bool) -> Self {
+        if half { Output::F16 } else { Output::F32 }
+    }
+
+    fn quantized<Q: QuantElement>(params: QuantParams) -> Result<Self> {
+        params.validate::<Q>()?;
+        if Q::MAX - Q::MIN > u8::MAX as i32 {
+            return Err(TensorizeError::InvalidConfig(format!(
+                "the GPU quantizes to 8 bits, {}..={} does not fit",
+                Q::MIN,
+                Q::MAX
+            )));
+        }
+        Ok(Output::Quant8 {
+            scale: params.scale.to_bits(),
+            zero_point: params.zero_point,
+            min: Q::MIN,
+            max: Q::MAX,
+        })
+    }
+
+    /// Tensor values per `u32` the shader writes.
+    fn per_word(self) -> usize {
+        match self {
+            Output::F32 => 1,
+            Output::F16 => 2,
+            Output::Quant8 { .. } => 4,
+        }
+    }
+}
+
+/// The GPU resources for tensorizing one image of a given size. The uniform
+/// parameters only depend on the size and output, so they are written once.
+struct ImageSlot {
+    input_texture: wgpu::Texture,
+    output_buffer: wgpu::Buffer,
+    bind_group: wgpu::BindGroup,
     output_width: u32,
     output_height: u32,
-    #[allow(unused)]
-    crop: Option<u32>,
-    mean: [f32; 3],
-    avg: [f32; 3],
 }
 
-fn create_catmull_rom_shader(device: &wgpu::Device) -> ShaderModule {
+fn create_tensor_shader(device: &wgpu::Device) -> ShaderModule {
     device.create_shader_module(include_wgsl!("im2tensor.wgsl"))
 }
 
-impl GpuTensorizer {
-    async fn new(
-        output_width: u32,
-        output_height: u32,
-        crop: Option<u32>,
-        mean: [f32; 3],
-        avg: [f32; 3],
-    ) -> anyhow::Result<Self> {
-        let instance = wgpu::Instance::new(&wgpu::InstanceDescriptor::default());
-        let adapter = instance
-            .request_adapter(&wgpu::RequestAdapterOptions {
-                power_preference: wgpu::PowerPreference::default(),
-                compatible_surface: None,
-                force_fallback_adapter: false,
-            })
-            .await?;
+/// The shader's code for `filter`.
+fn shader_filter(filter: FilterType) -> u32 {
+    match filter {
+        FilterType::Nearest => 0,
+        FilterType::Triangle => 1,
+        FilterType::CatmullRom => 2,
+        FilterType::Gaussian => 3,
+        FilterType::Lanczos3 => 4,
+    }
+}
 
-        let (device, queue) = adapter
-            .request_device(&wgpu::DeviceDescriptor {
-                label: None,
-                required_features: wgpu::Features::empty(),
-                required_limits: wgpu::Limits::default(),
-                memory_hints: wgpu::MemoryHints::Performance,
-                trace: wgpu::Trace::default(),
-            })
+/// Size in bytes of one tensor with `elements` values written as `output`.
+fn tensor_bytes(elements: usize, output: Output) -> u64 {
+    elements.div_ceil(output.per_word()) as u64 * 4
+}
+
+/// Waits `duration` on a helper thread, so no particular async runtime is needed.
+async fn delay(duration: Duration) {
+    let (sender, receiver) = tokio::sync::oneshot::channel();
+    std::thread::spawn(move || {
+        std::thread::sleep(duration);
+        let _ = sender.send(());
+    });
+    let _ = receiver.await;
+}
+
+impl GpuTensorizer {
+    async fn new(conv: ImageConvert) -> Result<Self> {
+        GpuTensorizer::with_options(conv, GpuOptions::default()).await
+    }
+    /// Creates a tensorizer on the adapter described by `options`.
+    pub async fn with_options(conv: ImageConvert, options: GpuOptions) -> Result<Self> {
+        conv.validate()?;
+        let context = GpuContext::with_options(options.clone()).await?;
+        let mut tensorizer = GpuTensorizer::with_context(&context, conv)?;
+        tensorizer.options = Some(options);
+        Ok(tensorizer)
+    }
+    /// Creates a tensorizer on the device of `context`, so its output can be
+    /// used by other work on the same device.
+    pub fn with_context(context: &GpuContext, conv: ImageConvert) -> Result<Self> {
+        Ok(GpuTensorizer {
+            state: RwLock::new(Arc::new(DeviceState::new(context, conv)?)),
+            conv,
+            options: None,
+            recovery: RecoveryPolicy::default(),
+            recovering: tokio::sync::Mutex::new(()),
+        })
+    }
+    /// Creates a tensorizer on an existing device, so its output can be
+    /// used by other work on the same device. See [`GpuContext::from_device`].
+    pub fn from_device(device: Arc<Device>, queue: Arc<Queue>, conv: ImageConvert) -> Result<Self> {
+        GpuTensorizer::with_context(&GpuContext::from_device(device, queue), conv)
+    }
+    /// Sets how a lost device is replaced.
+    pub fn recovery_policy(mut self, recovery: RecoveryPolicy) -> Self {
+        self.recovery = recovery;
+        self
+    }
+    /// The context this tensorizer currently runs on.
+    pub fn context(&self) -> GpuContext {
+        self.state().context.clone()
+    }
+    /// Describes the adapter in use, unless it was created from an existing device.
+    pub fn adapter_info(&self) -> Option<wgpu::AdapterInfo> {
+        self.state().context.adapter_info()
+    }
+    /// Tensorizes `imgs` written as `output`, passing every value read back
+    /// through `convert`. Quantized values are read back as integral `f32`.
+    async fn tensorize_with_batch<T: Copy + Default>(
+        &self,
+        imgs: &[DynamicImage],
+        output: Output,
+        convert: impl Fn(f32) -> T,
+    ) -> Result<Array4<T>> {
+        let state = self.state();
+        match state.tensorize_batch(imgs, output, &convert).await {
+            Err(TensorizeError::DeviceLost(message)) => {
+                self.recover_from(&state, message).await?;
+                self.state().tensorize_batch(imgs, output, &convert).await
+            }
+            result => result,
+        }
+    }
+    /// Tensorizes `images` into a buffer that stays on the GPU.
+    ///
+    /// The work is submitted but not awaited: anything submitted to the same
+    /// queue afterwards sees the finished tensor. The buffer can be bound as
+    /// storage or copied from. f16 values are packed in pairs, so an f16
+    /// batch needs an even number of values per image to stay contiguous.
+    ///
+    /// A lost device is reported rather than replaced here, see [`recover`](Self::recover).
+    pub fn tensorize_to_buffer(&self, images: &[DynamicImage], dtype: DType) -> Result<GpuTensor> {
+        self.state().tensorize_to_buffer(images, dtype)
+    }
+    /// Replaces the device if it was lost. The other tensorize calls do this
+    /// on their own.
+    pub async fn recover(&self) -> Result<()> {
+        let state = self.state();
+        match state.context.detect_loss() {
+            Err(TensorizeError::DeviceLost(message)) => self.recover_from(&state, message).await,
+            result => result,
+        }
+    }
+    fn state(&self) -> Arc<DeviceState> {
+        self.state.read().unwrap().clone()
+    }
+    /// Replaces `lost` with a state on a new device, unless another call
+    /// already did.
+    async fn recover_from(&self, lost: &Arc<DeviceState>, message: String) -> Result<()> {
+        let _recovering = self.recovering.lock().await;
+        if !Arc::ptr_eq(&self.state(), lost) {
+            return Ok(());
+        }
+        let Some(options) = &self.options else {
+            return Err(TensorizeError::DeviceLost(message));
+        };
+        let mut err = TensorizeError::DeviceLost(message);
+        let mut backoff = self.recovery.backoff;
+        for attempt in 1..=self.recovery.max_attempts {
+            log::warn!(
+                "replacing the GPU device, attempt {attempt} of {}: {err}",
+                self.recovery.max_attempts
+            );
+            delay(backoff).await;
+            backoff *= 2;
+            let state = GpuContext::with_options(options.clone())
+                .await
+                .and_then(|context| DeviceState::new(&context, self.conv));
+            match state {
+                Ok(state) => {
+                    *self.state.write().unwrap() = Arc::new(state);
+                    return Ok(());
+                }
+                Err(next) => err = next,
+            }
+        }
+        Err(err)
+    }
+    async fn tensorize<T: TensorElement>(&self, img: &DynamicImage) -> Result<Array3<T>> {
+        let a4 = self
+            .tensorize_with_batch(
+                std::slice::from_ref(img),
+                Output::float(T::GPU_HALF),
+                T::from_f32,
+            )
             .await?;
+        Ok(a4.index_axis_move(ndarray::Axis(0), 0))
+    }
+}
 
-        // Create textures for input and output
+impl DeviceState {
+    fn new(context: &GpuContext, conv: ImageConvert) -> Result<Self> {
+        conv.validate()?;
+        let interpolation = shader_filter(conv.interpolation);
+        let device = context.device();
 
         // Create bind group layout and bind group
         let bind_group_layout = device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
@@ -99,10 +406,10 @@ impl GpuTensorizer {
                 wgpu::BindGroupLayoutEntry {
                     binding: 1,
                     visibility: wgpu::ShaderStages::COMPUTE,
-                    ty: wgpu::BindingType::StorageTexture {
-                        access: wgpu::StorageTextureAccess::WriteOnly,
-                        format: wgpu::TextureFormat::Rgba32Float,
-                        view_dimension: wgpu::TextureViewDimension::D2,
+                    ty: wgpu::BindingType::Buffer {
+                        ty: wgpu::BufferBindingType::Storage { read_only: false },
+                        has_dynamic_offset: false,
+                        min_binding_size: None,
                     },
                     count: None,
                 },
@@ -125,7 +432,7 @@ impl GpuTensorizer {
             bind_group_layouts: &[&bind_group_layout],
             push_constant_ranges: &[],
         });
-        let shader = create_catmull_rom_shader(&device);
+        let shader = create_tensor_shader(device);
 
         let compute_pipeline = device.create_compute_pipeline(&wgpu::ComputePipelineDescriptor {
             label: Some("Resize Compute Pipeline"),
@@ -135,67 +442,426 @@ impl GpuTensorizer {
             compilation_options: Default::default(),
             cache: None,
         });
-        Ok(GpuTensorizer {
-            device,
-            queue,
+        let packed_pipeline = device.create_compute_pipeline(&wgpu::ComputePipelineDescriptor {
+            label: Some("Packed Resize Compute Pipeline"),
+            layout: Some(&pipeline_layout),
+            module: &shader,
+            entry_point: Some("main_packed"),
+            compilation_options: Default::default(),
+            cache: None,
+        });
+        let quantized_pipeline = device.create_compute_pipeline(&wgpu::ComputePipelineDescriptor {
+            label: Some("Quantized Resize Compute Pipeline"),
+            layout: Some(&pipeline_layout),
+            module: &shader,
+            entry_point: Some("main_quantized"),
+            compilation_options: Default::default(),
+            cache: None,
+        });
+        Ok(DeviceState {
+            context: context.clone(),
             bind_group_layout,
             compute_pipeline,
-            output_width,
-            output_height,
-            crop,
-            mean,
-            avg,
+            packed_pipeline,
+            quantized_pipeline,
+            conv,
+            interpolation,
+            slots: Mutex::new(HashMap::new()),
+            staging: Mutex::new(Vec::new()),
         })
     }
-    async fn tensorize_with_batch(&self, img: &DynamicImage) -> anyhow::Result<Array4<f32>> {
-        let a3 = self.tensorize(img).await?;
-        let a4 = a3.insert_axis(ndarray::Axis(0));
-        Ok(a4)
+    /// Runs one batch on this device, see [`GpuTensorizer::tensorize_with_batch`].
+    async fn tensorize_batch<T: Copy + Default>(
+        &self,
+        imgs: &[DynamicImage],
+        output: Output,
+        convert: &impl Fn(f32) -> T,
+    ) -> Result<Array4<T>> {
+        let (infos, shape, image_bytes) = self.batch_layout(imgs, output)?;
+        let elements: usize = shape.iter().product();
+        let batch_size = imgs.len();
+
+        // A failed submission would leave the pooled staging buffer with the
+        // previous call's results, so errors fail the call. The pooled
+        // resources may be what failed, so they are dropped with it.
+        let (output_buffer, slots) = self.context.scoped(|| {
+            // One staging buffer holds the results of every image in the batch
+            let output_buffer = self.take_staging(image_bytes * batch_size as u64);
+            let mut encoder =
+                self.context
+                    .device()
+                    .create_command_encoder(&wgpu::CommandEncoderDescriptor {
+                        label: Some("Resize Command Encoder"),
+                    });
+            // The slots are owned by this call until the readback is done
+            let slots = self.encode_batch(
+                &mut encoder,
+                imgs,
+                &infos,
+                output,
+                image_bytes,
+                &output_buffer,
+            );
+            self.context
+                .queue()
+                .submit(std::iter::once(encoder.finish()));
+            (output_buffer, slots)
+        })?;
+
+        // Read back the output buffer
+        let buffer_slice = output_buffer.slice(..);
+
+        let mapped = self.context.map_read(buffer_slice).await;
+        if mapped.is_err() {
+            self.context.detect_loss()?;
+        }
+        mapped?;
+
+        let data = buffer_slice.get_mapped_range();
+        let mut values = Vec::with_capacity(batch_size * elements);
+        for image in data.chunks_exact(image_bytes as usize) {
+            match output {
+                Output::F32 => {
+                    let image: &[f32] = bytemuck::cast_slice(image);
+                    values.extend(image.iter().map(|&value| convert(value)));
+                }
+                Output::F16 => {
+                    let image: &[f16] = bytemuck::cast_slice(image);
+                    values.extend(
+                        image[..elements]
+                            .iter()
+                            .map(|value| convert(value.to_f32())),
+                    );
+                }
+                Output::Quant8 { min, .. } => {
+                    // Signed values are stored in two's complement
+                    values.extend(image[..elements].iter().map(|&byte| {
+                        let value = if min < 0 {
+                            byte as i8 as i32
+                        } else {
+                            byte as i32
+                        };
+                        convert(value as f32)
+                    }));
+                }
+            }
+        }
+
+        drop(data);
+        output_buffer.unmap();
+        self.return_staging(output_buffer);
+        self.return_slots(slots, output);
+
+        let [d0, d1, d2] = shape;
+        Ok(Array4::from_shape_vec((batch_size, d0, d1, d2), values)?)
     }
-    async fn tensorize(&self, img: &DynamicImage) -> anyhow::Result<Array3<f32>> {
-        let (input_width, input_height) = img.dimensions();
-        let rgba_img = img.to_rgba8();
-        let img_data = rgba_img.into_raw();
-        // Create textures for input and output
+    /// Runs [`GpuTensorizer::tensorize_to_buffer`] on this device.
+    fn tensorize_to_buffer(&self, images: &[DynamicImage], dtype: DType) -> Result<GpuTensor> {
+        let output = Output::float(dtype == DType::F16);
+        let (infos, shape, image_bytes) = self.batch_layout(images, output)?;
+        let elements: usize = shape.iter().product();
+        if output == Output::F16 && elements % 2 == 1 && images.len() > 1 {
+            return Err(TensorizeError::InvalidConfig(format!(
+                "an f16 batch needs an even number of values per image, got {elements}"
+            )));
+        }
+        let (buffer, slots) = self.context.scoped(|| {
+            let buffer = self
+                .context
+                .device()
+                .create_buffer(&wgpu::BufferDescriptor {
+                    label: Some("Tensor Buffer"),
+                    size: image_bytes * images.len() as u64,
+                    usage: wgpu::BufferUsages::STORAGE
+                        | wgpu::BufferUsages::COPY_SRC
+                        | wgpu::BufferUsages::COPY_DST,
+                    mapped_at_creation: false,
+                });
+
+            let mut encoder =
+                self.context
+                    .device()
+                    .create_command_encoder(&wgpu::CommandEncoderDescriptor {
+                        label: Some("Resize Command Encoder"),
+                    });
+            let slots =
+                self.encode_batch(&mut encoder, images, &infos, output, image_bytes, &buffer);
+            self.context
+                .queue()
+                .submit(std::iter::once(encoder.finish()));
+            (buffer, slots)
+        })?;
+        // Later writes to the slots are queued behind this submission
+        self.return_slots(slots, output);
+
+        let [d0, d1, d2] = shape;
+        Ok(GpuTensor {
+            buffer,
+            shape: [images.len(), d0, d1, d2],
+            dtype,
+        })
+    }
+    /// Validates `imgs` and returns where each one is placed, the shape of
+    /// one tensor and its size in bytes.
+    fn batch_layout(
+        &self,
+        imgs: &[DynamicImage],
+        output: Output,
+    ) -> Result<(Vec<LetterboxInfo>, [usize; 3], u64)> {
+        if imgs.is_empty() {
+            return Err(TensorizeError::EmptyBatch);
+        }
+        self.context.detect_loss()?;
+        let max = self.context.device().limits().max_texture_dimension_2d;
+        let mut infos = Vec::with_capacity(imgs.len());
+        for img in imgs {
+            let (width, height) = img.dimensions();
+            if width == 0 || height == 0 {
+                return Err(TensorizeError::EmptyImage);
+            }
+            if width > max || height > max {
+                return Err(TensorizeError::ImageTooLarge { width, height, max });
+            }
+            infos.push(self.conv.letterbox_info(width, height));
+        }
+        let (output_width, output_height) = (infos[0].width, infos[0].height);
+        if infos
+            .iter()
+            .any(|info| (info.width, info.height) != (output_width, output_height))
+        {
+            return Err(TensorizeError::InvalidConfig(
+                "images in a batch produce tensors of different sizes".to_string(),
+            ));
+        }
+
+        // The shader writes each image as the final tensor, so the readback
+        // needs no unpacking
+        let shape = self.conv.tensor_shape(output_width, output_height);
+        let elements: usize = shape.iter().product();
+        let image_bytes = tensor_bytes(elements, output);
+        let max_binding = self
+            .context
+            .device()
+            .limits()
+            .max_storage_buffer_binding_size as u64;
+        if image_bytes > max_binding {
+            return Err(TensorizeError::InvalidConfig(format!(
+                "a {output_width}x{output_height} tensor needs {image_bytes} bytes, \
+                 the device binds at most {max_binding}"
+            )));
+        }
+        Ok((infos, shape, image_bytes))
+    }
+    /// Records every image of the batch into `encoder` and copies tensor `i`
+    /// to `destination` at `i * image_bytes`. Returns the slots in use.
+    fn encode_batch(
+        &self,
+        encoder: &mut wgpu::CommandEncoder,
+        imgs: &[DynamicImage],
+        infos: &[LetterboxInfo],
+        output: Output,
+        image_bytes: u64,
+        destination: &wgpu::Buffer,
+    ) -> Vec<(u32, u32, ImageSlot)> {
+        let mut slots = Vec::with_capacity(imgs.len());
+        for (i, (img, info)) in imgs.iter().zip(infos).enumerate() {
+            let slot = self.take_slot(info, output);
+            self.encode_image(encoder, &slot, img, output);
+
+            // Copy the tensor to its place in the destination buffer
+            encoder.copy_buffer_to_buffer(
+                &slot.output_buffer,
+                0,
+                destination,
+                image_bytes * i as u64,
+                image_bytes,
+            );
+            slots.push((info.input_width, info.input_height, slot));
+        }
+        slots
+    }
+    /// Takes pooled resources for an image described by `info`, creating
+    /// them on first use of its size.
+    fn take_slot(&self, info: &LetterboxInfo, output: Output) -> ImageSlot {
+        let key = (info.input_width, info.input_height, output);
+        let pooled = self
+            .slots
+            .lock()
+            .unwrap()
+            .get_mut(&key)
+            .and_then(|slots| slots.pop());
+        pooled.unwrap_or_else(|| self.create_slot(info, output))
+    }
+    fn return_slots(&self, slots: Vec<(u32, u32, ImageSlot)>, output: Output) {
+        let mut pool = self.slots.lock().unwrap();
+        for (width, height, slot) in slots {
+            let key = (width, height, output);
+            // Start over rather than grow without bound on ever-changing sizes
+            if !pool.contains_key(&key) && pool.len() >= MAX_POOLED_SIZES {
+                pool.clear();
+            }
+            pool.entry(key).or_default().push(slot);
+        }
+    }
+    /// Takes an idle staging buffer of `size` bytes or creates one.
+    fn take_staging(&self, size: u64) -> wgpu::Buffer {
+        let mut staging = self.staging.lock().unwrap();
+        match staging.iter().position(|buffer| buffer.size() == size) {
+            Some(index) => staging.swap_remove(index),
+            None => self
+                .context
+                .device()
+                .create_buffer(&wgpu::BufferDescriptor {
+                    label: Some("Output Buffer"),
+                    size,
+                    usage: wgpu::BufferUsages::COPY_DST | wgpu::BufferUsages::MAP_READ,
+                    mapped_at_creation: false,
+                }),
+        }
+    }
+    fn return_staging(&self, buffer: wgpu::Buffer) {
+        let mut staging = self.staging.lock().unwrap();
+        if staging.len() >= MAX_POOLED_STAGING {
+            staging.remove(0);
+        }
+        staging.push(buffer);
+    }
+    /// Creates the input texture, output buffer, parameters and bind group
+    /// for images described by `info`.
+    fn create_slot(&self, info: &LetterboxInfo, output: Output) -> ImageSlot {
+        let (input_width, input_height) = (info.input_width, info.input_height);
+        // Create the input texture
         let texture_size = wgpu::Extent3d {
             width: input_width,
             height: input_height,
             depth_or_array_layers: 1,
         };
 
-        let input_texture = self.device.create_texture(&wgpu::TextureDescriptor {
-            label: Some("Input Texture"),
-            size: texture_size,
-            mip_level_count: 1,
-            sample_count: 1,
-            dimension: wgpu::TextureDimension::D2,
-            format: wgpu::TextureFormat::Rgba8Unorm,
-            usage: wgpu::TextureUsages::TEXTURE_BINDING | wgpu::TextureUsages::COPY_DST,
-            view_formats: &[],
-        });
+        let input_texture = self
+            .context
+            .device()
+            .create_texture(&wgpu::TextureDescriptor {
+                label: Some("Input Texture"),
+                size: texture_size,
+                mip_level_count: 1,
+                sample_count: 1,
+                dimension: wgpu::TextureDimension::D2,
+                format: wgpu::TextureFormat::Rgba8Unorm,
+                usage: wgpu::TextureUsages::TEXTURE_BINDING | wgpu::TextureUsages::COPY_DST,
+                view_formats: &[],
+            });
 
-        // Create output texture
-        let output_texture_size = wgpu::Extent3d {
-            width: self.output_width,
-            height: self.output_height,
-            depth_or_array_layers: 1,
+        // Create the output buffer holding the tensor in its final layout
+        let elements = self
+            .conv
+            .tensor_shape(info.width, info.height)
+            .iter()
+            .product();
+        let output_buffer = self
+            .context
+            .device()
+            .create_buffer(&wgpu::BufferDescriptor {
+                label: Some("Tensor Buffer"),
+                size: tensor_bytes(elements, output),
+                usage: wgpu::BufferUsages::STORAGE
+                    | wgpu::BufferUsages::COPY_SRC
+                    | wgpu::BufferUsages::COPY_DST,
+                mapped_at_creation: false,
+            });
+
+        let [r, g, b] = self.conv.mean;
+        let mean = [r, g, b, 0.0];
+        let [r, g, b] = self.conv.std;
+        let avg = [r, g, b, 0.0];
+        let [r, g, b] = self.conv.fill.map(|c| c as f32 / 255.0);
+        let fill = [r, g, b, 0.0];
+        let (quant_scale, zero_point, quant_min, quant_max) = match output {
+            Output::Quant8 {
+                scale,
+                zero_point,
+                min,
+                max,
+            } => (f32::from_bits(scale), zero_point, min, max),
+            Output::F32 | Output::F16 => (1.0, 0, 0, 0),
+        };
+        // Create the resize parameters buffer
+        let resize_params = TensorParams {
+            input_width,
+            input_height,
+            output_width: info.width,
+            output_height: info.height,
+            resize_width: info.resized_width,
+            resize_height: info.resized_height,
+            crop_x: -info.pad_x,
+            crop_y: -info.pad_y,
+            channels: self.conv.channels as u32,
+            bgr: (self.conv.channel_order == ChannelOrder::Bgr) as u32,
+            // Texels are sampled in 0..1, the input scale applies to 0..255
+            scale: self.conv.input_scale * 255.0,
+            channels_last: self.conv.layout.channels_last() as u32,
+            quant_scale,
+            zero_point,
+            quant_min,
+            quant_max,
+            interpolation: self.interpolation,
+            _padding: [0; 3],
+            mean,
+            avg,
+            fill,
         };
 
-        let output_texture = self.device.create_texture(&wgpu::TextureDescriptor {
-            label: Some("Output Texture"),
-            size: output_texture_size,
-            mip_level_count: 1,
-            sample_count: 1,
-            dimension: wgpu::TextureDimension::D2,
-            format: wgpu::TextureFormat::Rgba32Float,
-            usage: wgpu::TextureUsages::STORAGE_BINDING | wgpu::TextureUsages::COPY_SRC,
-            view_formats: &[],
-        });
+        let resize_params_buffer = self
+            .context
+            .uniform_buffer("Resize Parameters Buffer", &resize_params);
+
+        // Create bind group
+        let bind_group = self
+            .context
+            .device()
+            .create_bind_group(&wgpu::BindGroupDescriptor {
+                label: Some("Resize Bind Group"),
+                layout: &self.bind_group_layout,
+                entries: &[
+                    wgpu::BindGroupEntry {
+                        binding: 0,
+                        resource: wgpu::BindingResource::TextureView(
+                            &input_texture.create_view(&wgpu::TextureViewDescriptor::default()),
+                        ),
+                    },
+                    wgpu::BindGroupEntry {
+                        binding: 1,
+                        resource: output_buffer.as_entire_binding(),
+                    },
+                    wgpu::BindGroupEntry {
+                        binding: 2,
+                        resource: resize_params_buffer.as_entire_binding(),
+                    },
+                ],
+            });
+        ImageSlot {
+            input_texture,
+            output_buffer,
+            bind_group,
+            output_width: info.width,
+            output_height: info.height,
+        }
+    }
+    /// Uploads `img` into `slot` and records its resize/normalize dispatch into `encoder`.
+    fn encode_image(
+        &self,
+        encoder: &mut wgpu::CommandEncoder,
+        slot: &ImageSlot,
+        img: &DynamicImage,
+        output: Output,
+    ) {
+        let (input_width, input_height) = img.dimensions();
+        let rgba_img = img.to_rgba8();
+        let img_data = rgba_img.into_raw();
 
         // Upload image data to the input texture
-        self.queue.write_texture(
+        self.context.queue().write_texture(
             wgpu::TexelCopyTextureInfo {
-                texture: &input_texture,
+                texture: &slot.input_texture,
                 mip_level: 0,
                 origin: wgpu::Origin3d::ZERO,
                 aspect: wgpu::TextureAspect::All,
@@ -206,160 +872,87 @@ impl GpuTensorizer {
                 bytes_per_row: Some(NonZeroU32::new(4 * input_width).unwrap().into()),
                 rows_per_image: Some(NonZeroU32::new(input_height).unwrap().into()),
             },
-            texture_size,
+            slot.input_texture.size(),
         );
-        let [r, g, b] = self.mean;
-        let mean = [r, g, b, 0.0];
-        let [r, g, b] = self.avg;
-        let avg = [r, g, b, 0.0];
-        // Create the resize parameters buffer
-        let resize_params = TensorParams {
-            input_width,
-            input_height,
-            output_width: self.output_width,
-            output_height: self.output_height,
-            mean,
-            avg,
-        };
-
-        let resize_params_buffer =
-            self.device
-                .create_buffer_init(&wgpu::util::BufferInitDescriptor {
-                    label: Some("Resize Parameters Buffer"),
-                    contents: bytemuck::cast_slice(&[resize_params]),
-                    usage: wgpu::BufferUsages::UNIFORM,
-                });
-
-        // Create bind group
-        let bind_group = self.device.create_bind_group(&wgpu::BindGroupDescriptor {
-            label: Some("Resize Bind Group"),
-            layout: &self.bind_group_layout,
-            entries: &[
-                wgpu::BindGroupEntry {
-                    binding: 0,
-                    resource: wgpu::BindingResource::TextureView(
-                        &input_texture.create_view(&wgpu::TextureViewDescriptor::default()),
-                    ),
-                },
-                wgpu::BindGroupEntry {
-                    binding: 1,
-                    resource: wgpu::BindingResource::TextureView(
-                        &output_texture.create_view(&wgpu::TextureViewDescriptor::default()),
-                    ),
-                },
-                wgpu::BindGroupEntry {
-                    binding: 2,
-                    resource: resize_params_buffer.as_entire_binding(),
-                },
-            ],
-        });
 
-        // Execute the compute shader
-        let mut encoder = self
-            .device
-            .create_command_encoder(&wgpu::CommandEncoderDescriptor {
-                label: Some("Resize Command Encoder"),
-            });
+        // Packed values are OR-ed together, so they have to start out zeroed
+        if output != Output::F32 {
+            encoder.clear_buffer(&slot.output_buffer, 0, None);
+        }
 
+        // Record the compute shader dispatch
         {
             let mut compute_pass = encoder.begin_compute_pass(&wgpu::ComputePassDescriptor {
                 label: Some("Resize Compute Pass"),
                 timestamp_writes: None,
             });
-            compute_pass.set_pipeline(&self.compute_pipeline);
-            compute_pass.set_bind_group(0, &bind_group, &[]);
+            compute_pass.set_pipeline(match output {
+                Output::F32 => &self.compute_pipeline,
+                Output::F16 => &self.packed_pipeline,
+                Output::Quant8 { .. } => &self.quantized_pipeline,
+            });
+            compute_pass.set_bind_group(0, &slot.bind_group, &[]);
             compute_pass.dispatch_workgroups(
-                (self.output_width + 15) / 16,
-                (self.output_height + 15) / 16,
+                slot.output_width.div_ceil(16),
+                slot.output_height.div_ceil(16),
                 1,
             );
         }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use image::{Rgba, RgbaImage};
 
-        // Calculate bytes_per_row with proper alignment (256 bytes)
-        let align = 256;
-        let bytes_per_pixel = 16; // RGBAFloat32 = 16 bytes per pixel
-        let unpadded_bytes_per_row = self.output_width * bytes_per_pixel;
-        let padding = (align - unpadded_bytes_per_row % align) % align;
-        let padded_bytes_per_row = unpadded_bytes_per_row + padding;
-
-        // Create output buffer to retrieve the resized image data
-        let output_buffer_size = padded_bytes_per_row as u64 * self.output_height as u64;
-        let output_buffer = self.device.create_buffer(&wgpu::BufferDescriptor {
-            label: Some("Output Buffer"),
-            size: output_buffer_size,
+    use super::*;
+    use crate::cpu_tensor::Layout;
+
+    /// Copies `tensor` into a mappable buffer and reads its raw values.
+    async fn read_buffer(context: &GpuContext, tensor: &GpuTensor) -> Vec<f32> {
+        let size = tensor.buffer.size();
+        let readback = context.device().create_buffer(&wgpu::BufferDescriptor {
+            label: None,
+            size,
             usage: wgpu::BufferUsages::COPY_DST | wgpu::BufferUsages::MAP_READ,
             mapped_at_creation: false,
         });
+        let mut encoder = context
+            .device()
+            .create_command_encoder(&wgpu::CommandEncoderDescriptor::default());
+        encoder.copy_buffer_to_buffer(&tensor.buffer, 0, &readback, 0, size);
+        context.queue().submit(std::iter::once(encoder.finish()));
+        context.map_read(readback.slice(..)).await.unwrap();
+        bytemuck::cast_slice(&readback.slice(..).get_mapped_range()).to_vec()
+    }
 
-        // Copy the output texture to the output buffer
-        encoder.copy_texture_to_buffer(
-            wgpu::TexelCopyTextureInfo {
-                texture: &output_texture,
-                mip_level: 0,
-                origin: wgpu::Origin3d::ZERO,
-                aspect: wgpu::TextureAspect::All,
-            },
-            wgpu::TexelCopyBufferInfo {
-                buffer: &output_buffer,
-                layout: wgpu::TexelCopyBufferLayout {
-                    offset: 0,
-                    bytes_per_row: Some(NonZeroU32::new(padded_bytes_per_row).unwrap().into()),
-                    rows_per_image: Some(NonZeroU32::new(self.output_height).unwrap().into()),
-                },
-            },
-            output_texture_size,
-        );
-
-        self.queue.submit(std::iter::once(encoder.finish()));
-
-        // Read back the output buffer
-        let buffer_slice = output_buffer.slice(..);
-
-        buffer_slice.map_async(wgpu::MapMode::Read, move |_| {});
-
-        let _ = self.device.poll(wgpu::PollType::Wait)?;
-
-        let data = buffer_slice.get_mapped_range();
-        let mut tensor =
-            Array3::<f32>::zeros((3, self.output_height as usize, self.output_width as usize));
-        for y in 0..self.output_height as usize {
-            for x in 0..self.output_width as usize {
-                let row_start = y * padded_bytes_per_row as usize;
-                let pixel_start = row_start + (x * bytes_per_pixel as usize);
-
-                // Read RGBA float values (each float is 4 bytes)
-                let r = f32::from_ne_bytes([
-                    data[pixel_start],
-                    data[pixel_start + 1],
-                    data[pixel_start + 2],
-                    data[pixel_start + 3],
-                ]);
-
-                let g = f32::from_ne_bytes([
-                    data[pixel_start + 4],
-                    data[pixel_start + 5],
-                    data[pixel_start + 6],
-                    data[pixel_start + 7],
-                ]);
-
-                let b = f32::from_ne_bytes([
-                    data[pixel_start + 8],
-                    data[pixel_start + 9],
-                    data[pixel_start + 10],
-                    data[pixel_start + 11],
-                ]);
-
-                // Store in CHW format with ImageNet normalization
-                // ImageNet normalization: values are in [0,1] range
-                tensor[[0, y, x]] = r; // R channel
-                tensor[[1, y, x]] = g; // G channel
-                tensor[[2, y, x]] = b; // B channel
-            }
-        }
-
-        drop(data);
-        output_buffer.unmap();
-
-        Ok(tensor)
+    #[tokio::test]
+    async fn shader_writes_channels_last() {
+        let image = DynamicImage::ImageRgba8(RgbaImage::from_fn(40, 30, |x, y| {
+            Rgba([(x * 6) as u8, (y * 8) as u8, (x * y) as u8, 255])
+        }));
+        let chw = ImageConvert::builder().resize(20, 12).build().unwrap();
+        let Ok(planar) = GpuTensorizer::new(chw).await else {
+            eprintln!("no GPU available, skipping");
+            return;
+        };
+        let nhwc = ImageConvert {
+            layout: Layout::Nhwc,
+            ..chw
+        };
+        let interleaved = GpuTensorizer::with_context(&planar.context(), nhwc).unwrap();
+
+        let expected: Array3<f32> = planar.tensorize(&image).await.unwrap();
+        let expected = expected.permuted_axes([1, 2, 0]);
+        let actual: Array3<f32> = interleaved.tensorize(&image).await.unwrap();
+        assert_eq!(actual, expected);
+
+        // The buffer on the GPU is already interleaved
+        let tensor = interleaved
+            .tensorize_to_buffer(std::slice::from_ref(&image), DType::F32)
+            .unwrap();
+        assert_eq!(tensor.shape, [1, 12, 20, 3]);
+        let values = read_buffer(&planar.context(), &tensor).await;
+        assert_eq!(values, actual.as_slice().unwrap());
     }
 }
@@ -0,0 +1,343 @@
+//! Hugging Face `preprocessor_config.json` support.
+
+use std::path::Path;
+
+use image::imageops::FilterType;
+use serde::Deserialize;
+
+use crate::{
+    cpu_tensor::{ImageConvert, ResizeMode},
+    error::{Result, TensorizeError},
+};
+
+/// A size entry, either a bare edge length or a dictionary of named edges.
+#[derive(Debug, Deserialize)]
+#[serde(untagged)]
+enum HfSize {
+    Edge(u32),
+    Named {
+        shortest_edge: Option<u32>,
+        longest_edge: Option<u32>,
+        height: Option<u32>,
+        width: Option<u32>,
+    },
+}
+
+/// Per-channel statistics, either one value for every channel or a list.
+#[derive(Debug, Deserialize)]
+#[serde(untagged)]
+enum HfChannels {
+    Scalar(f32),
+    List(Vec<f32>),
+}
+
+fn default_true() -> bool {
+    true
+}
+
+#[derive(Debug, Deserialize)]
+struct HfPreprocessorConfig {
+    image_processor_type: Option<String>,
+    feature_extractor_type: Option<String>,
+    #[serde(default = "default_true")]
+    do_resize: bool,
+    size: Option<HfSize>,
+    crop_pct: Option<f64>,
+    resample: Option<u32>,
+    #[serde(default)]
+    do_center_crop: bool,
+    crop_size: Option<HfSize>,
+    #[serde(default = "default_true")]
+    do_rescale: bool,
+    rescale_factor: Option<f32>,
+    #[serde(default = "default_true")]
+    do_normalize: bool,
+    image_mean: Option<HfChannels>,
+    image_std: Option<HfChannels>,
+}
+
+impl HfPreprocessorConfig {
+    /// Builds the config from the resolved resize and crop, adding the
+    /// rescale, normalization and resampling options.
+    fn build(
+        &self,
+        width: u32,
+        height: u32,
+        resize_mode: ResizeMode,
+        crop_width: u32,
+        crop_height: u32,
+    ) -> Result<ImageConvert> {
+        let input_scale = if self.do_rescale {
+            self.rescale_factor.unwrap_or(1.0 / 255.0)
+        } else {
+            1.0
+        };
+
+        let (mean, std) = if self.do_normalize {
+            (
+                channels("image_mean", self.image_mean.as_ref())?,
+                channels("image_std", self.image_std.as_ref())?,
+            )
+        } else {
+            ([0.0; 3], [1.0; 3])
+        };
+
+        ImageConvert::builder()
+            .resize(width, height)
+            .resize_mode(resize_mode)
+            .crop(crop_width, crop_height)
+            .mean(mean)
+            .std(std)
+            .input_scale(input_scale)
+            .interpolation(pil_filter(self.resample.unwrap_or(2))?)
+            .build()
+    }
+}
+
+fn unsupported(message: String) -> TensorizeError {
+    TensorizeError::InvalidConfig(message)
+}
+
+/// Maps a PIL resampling code onto the matching [`FilterType`].
+fn pil_filter(resample: u32) -> Result<FilterType> {
+    match resample {
+        0 => Ok(FilterType::Nearest),
+        1 => Ok(FilterType::Lanczos3),
+        2 => Ok(FilterType::Triangle),
+        3 => Ok(FilterType::CatmullRom),
+        4 => Err(unsupported(
+            "PIL resample BOX (4) is not supported".to_string(),
+        )),
+        5 => Err(unsupported(
+            "PIL resample HAMMING (5) is not supported".to_string(),
+        )),
+        other => Err(unsupported(format!("unknown PIL resample code {other}"))),
+    }
+}
+
+/// Processors whose bare integer `size` is the shortest edge
+/// (`default_to_square=False` in transformers).
+const SHORTEST_EDGE_PROCESSORS: &[&str] = &[
+    "Bit",
+    "CLIP",
+    "ChineseCLIP",
+    "ConvNext",
+    "MobileNetV1",
+    "MobileNetV2",
+    "MobileViT",
+];
+/// Processors whose bare integer `size` is a square.
+const SQUARE_PROCESSORS: &[&str] = &["Beit", "DeiT", "ViT"];
+
+/// The model name of the processor class, e.g. `CLIP` for `CLIPImageProcessor`.
+fn processor_name(config: &HfPreprocessorConfig) -> Option<&str> {
+    let class = config
+        .image_processor_type
+        .as_deref()
+        .or(config.feature_extractor_type.as_deref())?;
+    ["ImageProcessorFast", "ImageProcessor", "FeatureExtractor"]
+        .iter()
+        .find_map(|suffix| class.strip_suffix(suffix))
+}
+
+fn resize_size(size: &HfSize, processor: Option<&str>) -> Result<(u32, u32, ResizeMode)> {
+    match *size {
+        HfSize::Edge(edge) => match processor {
+            Some(name) if SHORTEST_EDGE_PROCESSORS.contains(&name) => {
+                Ok((edge, edge, ResizeMode::ShortestEdge))
+            }
+            Some(name) if SQUARE_PROCESSORS.contains(&name) => Ok((edge, edge, ResizeMode::Exact)),
+            // Whether a bare size is square or the shortest edge depends on the processor
+            _ => Err(unsupported(format!(
+                "size {edge} is ambiguous for processor {processor:?}, \
+                 use {{\"shortest_edge\": {edge}}} or {{\"height\": {edge}, \"width\": {edge}}}"
+            ))),
+        },
+        HfSize::Named {
+            height: Some(height),
+            width: Some(width),
+            ..
+        } => Ok((width, height, ResizeMode::Exact)),
+        HfSize::Named {
+            shortest_edge: Some(edge),
+            longest_edge: None,
+            ..
+        } => Ok((edge, edge, ResizeMode::ShortestEdge)),
+        HfSize::Named { .. } => Err(unsupported(format!("unsupported size {size:?}"))),
+    }
+}
+
+/// Resize box, mode and crop of ConvNextImageProcessor, which ignores
+/// `do_center_crop`: below 384 it resizes the shortest edge to
+/// `int(shortest_edge / crop_pct)` and center crops `shortest_edge`, from
+/// 384 on it squashes to a square.
+fn convnext_size(config: &HfPreprocessorConfig, size: &HfSize) -> Result<(u32, ResizeMode, u32)> {
+    let edge = match resize_size(size, Some("ConvNext"))? {
+        (edge, _, ResizeMode::ShortestEdge) => edge,
+        _ => return Err(unsupported(format!("unsupported ConvNext size {size:?}"))),
+    };
+    if edge >= 384 {
+        return Ok((edge, ResizeMode::Exact, edge));
+    }
+    let crop_pct = config.crop_pct.unwrap_or(224.0 / 256.0);
+    if !(crop_pct > 0.0 && crop_pct <= 1.0) {
+        return Err(unsupported(format!(
+            "crop_pct {crop_pct} must be in (0, 1]"
+        )));
+    }
+    Ok((
+        (edge as f64 / crop_pct) as u32,
+        ResizeMode::ShortestEdge,
+        edge,
+    ))
+}
+
+fn crop_size(size: &HfSize) -> Result<(u32, u32)> {
+    match *size {
+        HfSize::Edge(edge) => Ok((edge, edge)),
+        HfSize::Named {
+            height: Some(height),
+            width: Some(width),
+            ..
+        } => Ok((width, height)),
+        HfSize::Named { .. } => Err(unsupported(format!("unsupported crop_size {size:?}"))),
+    }
+}
+
+fn channels(name: &str, values: Option<&HfChannels>) -> Result<[f32; 3]> {
+    match values {
+        Some(HfChannels::Scalar(value)) => Ok([*value; 3]),
+        Some(HfChannels::List(values)) => match values[..] {
+            [value] => Ok([value; 3]),
+            [r, g, b] => Ok([r, g, b]),
+            _ => Err(unsupported(format!(
+                "{name} has {} entries, expected 1 or 3",
+                values.len()
+            ))),
+        },
+        None => Err(unsupported(format!("{name} is missing"))),
+    }
+}
+
+impl ImageConvert {
+    /// Reads a Hugging Face `preprocessor_config.json`.
+    pub fn from_hf_preprocessor_config(path: impl AsRef<Path>) -> Result<ImageConvert> {
+        let json = std::fs::read_to_string(path)?;
+        ImageConvert::from_hf_preprocessor_json(&json)
+    }
+
+    /// Parses the contents of a Hugging Face `preprocessor_config.json`.
+    pub fn from_hf_preprocessor_json(json: &str) -> Result<ImageConvert> {
+        let config: HfPreprocessorConfig = serde_json::from_str(json)?;
+
+        if !config.do_resize {
+            return Err(unsupported(
+                "do_resize = false is not supported".to_string(),
+            ));
+        }
+        let size = config
+            .size
+            .as_ref()
+            .ok_or_else(|| unsupported("size is missing".to_string()))?;
+        let processor = processor_name(&config);
+        if processor == Some("ConvNext") {
+            let (resize, resize_mode, crop) = convnext_size(&config, size)?;
+            return config.build(resize, resize, resize_mode, crop, crop);
+        }
+        if let Some(crop_pct) = config.crop_pct {
+            return Err(unsupported(format!(
+                "crop_pct {crop_pct} is only supported for ConvNext"
+            )));
+        }
+        let (width, height, resize_mode) = resize_size(size, processor)?;
+
+        let (crop_width, crop_height) = if config.do_center_crop {
+            let crop = config
+                .crop_size
+                .as_ref()
+                .ok_or_else(|| unsupported("crop_size is missing".to_string()))?;
+            crop_size(crop)?
+        } else if resize_mode == ResizeMode::ShortestEdge {
+            // The resized size depends on the aspect ratio, so without a crop
+            // the tensor has no fixed shape
+            return Err(unsupported(
+                "size.shortest_edge without do_center_crop is not supported".to_string(),
+            ));
+        } else {
+            (width, height)
+        };
+
+        config.build(width, height, resize_mode, crop_width, crop_height)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse(json: &str) -> Result<ImageConvert> {
+        ImageConvert::from_hf_preprocessor_json(json)
+    }
+
+    const STATS: &str = r#""image_mean": [0.5, 0.5, 0.5], "image_std": [0.5, 0.5, 0.5]"#;
+
+    #[test]
+    fn bare_size_follows_the_processor() {
+        let clip = parse(&format!(
+            r#"{{"feature_extractor_type": "CLIPFeatureExtractor", "size": 224,
+                "do_center_crop": true, "crop_size": 224, {STATS}}}"#
+        ))
+        .unwrap();
+        assert_eq!(clip.resize_mode, ResizeMode::ShortestEdge);
+        assert_eq!((clip.width, clip.crop_width), (224, 224));
+
+        let vit = parse(&format!(
+            r#"{{"image_processor_type": "ViTImageProcessor", "size": 224, {STATS}}}"#
+        ))
+        .unwrap();
+        assert_eq!(vit.resize_mode, ResizeMode::Exact);
+        assert_eq!((vit.width, vit.height), (224, 224));
+
+        let unknown = parse(&format!(r#"{{"size": 224, {STATS}}}"#));
+        assert!(matches!(unknown, Err(TensorizeError::InvalidConfig(_))));
+    }
+
+    #[test]
+    fn convnext_applies_crop_pct() {
+        let convnext = |size: u32, crop_pct: &str| {
+            parse(&format!(
+                r#"{{"feature_extractor_type": "ConvNextFeatureExtractor", "size": {size},
+                    {crop_pct} {STATS}}}"#
+            ))
+        };
+        let conv = convnext(224, r#""crop_pct": 0.875,"#).unwrap();
+        assert_eq!(conv.resize_mode, ResizeMode::ShortestEdge);
+        assert_eq!((conv.width, conv.crop_width), (256, 224));
+        // crop_pct defaults to 224 / 256
+        assert_eq!(convnext(224, "").unwrap(), conv);
+        let conv = convnext(288, r#""crop_pct": 0.9,"#).unwrap();
+        assert_eq!((conv.width, conv.crop_width), (320, 288));
+        // From 384 on the image is squashed without a crop
+        let conv = convnext(384, r#""crop_pct": 0.875,"#).unwrap();
+        assert_eq!(conv.resize_mode, ResizeMode::Exact);
+        assert_eq!((conv.width, conv.crop_width), (384, 384));
+        assert!(convnext(224, r#""crop_pct": 1.5,"#).is_err());
+    }
+
+    #[test]
+    fn rejects_unsupported_options() {
+        let crop_pct = parse(&format!(
+            r#"{{"image_processor_type": "ViTImageProcessor", "size": 224,
+                "crop_pct": 0.9, {STATS}}}"#
+        ));
+        assert!(matches!(crop_pct, Err(TensorizeError::InvalidConfig(_))));
+
+        let no_crop = parse(&format!(r#"{{"size": {{"shortest_edge": 224}}, {STATS}}}"#));
+        assert!(matches!(no_crop, Err(TensorizeError::InvalidConfig(_))));
+
+        let box_filter = parse(&format!(
+            r#"{{"size": {{"height": 224, "width": 224}}, "resample": 4, {STATS}}}"#
+        ));
+        assert!(matches!(box_filter, Err(TensorizeError::InvalidConfig(_))));
+    }
+}
@@ -1,7 +1,11 @@
-// catmull_rom.wgsl
+// im2tensor.wgsl: resizes, crops and normalizes an image into a tensor
 
 @group(0) @binding(0) var input_texture: texture_2d<f32>;
-@group(0) @binding(1) var output_texture: texture_storage_2d<rgba32float, write>;
+// The tensor in its final layout, written by `main`
+@group(0) @binding(1) var<storage, read_write> output: array<f32>;
+// The same tensor as pairs of packed f16 or quads of 8-bit integers,
+// written by `main_packed` and `main_quantized`
+@group(0) @binding(1) var<storage, read_write> packed: array<atomic<u32>>;
 @group(0) @binding(2) var<uniform> params: Params;
 
 struct Params {
@@ -9,8 +13,26 @@ struct Params {
     input_height: u32,
     output_width: u32,
     output_height: u32,
+    resize_width: u32,
+    resize_height: u32,
+    crop_x: i32,
+    crop_y: i32,
+    channels: u32,
+    bgr: u32,
+    scale: f32,
+    channels_last: u32,
+    quant_scale: f32,
+    zero_point: i32,
+    quant_min: i32,
+    quant_max: i32,
+    // 0 nearest, 1 triangle, 2 Catmull-Rom, 3 Gaussian, 4 Lanczos3
+    interpolation: u32,
+    _padding0: u32,
+    _padding1: u32,
+    _padding2: u32,
     mean: vec3<f32>,
     avg: vec3<f32>,
+    fill: vec4<f32>,
 }
 
 // Catmull-Rom interpolation weight calculation
@@ -40,20 +62,56 @@ fn catmull_rom_1d(p0: vec4<f32>, p1: vec4<f32>, p2: vec4<f32>, p3: vec4<f32>, t:
     return p0 * weights.x + p1 * weights.y + p2 * weights.z + p3 * weights.w;
 }
 
-fn normalize_old(color: vec4<f32>, mean: vec3<f32>, avg: vec3<f32>) -> vec4<f32> {
-    let r = (color.x - mean.x) / avg.x;
-    let g = (color.y - mean.y) / avg.y;
-    let b = (color.z - mean.z) / avg.z;
-    let a = color.w;
-
-    return vec4<f32>(r,g,b,a);
-}
-
 fn normalize(color: vec4<f32>, mean: vec3<f32>, avg: vec3<f32>) -> vec4<f32> {
     let rgb = (color.xyz - mean) / avg;
     return vec4<f32>(rgb, color.w);
 }
 
+// Reorder to the requested channel order and apply the input scale
+fn prepare(color: vec4<f32>) -> vec4<f32> {
+    var ordered = color;
+    if (params.bgr == 1u && params.channels >= 3u) {
+        ordered = color.zyxw;
+    }
+    return ordered * params.scale;
+}
+
+// Rec. 709 luma in the first channel, matching image's to_luma8
+fn to_luma(color: vec4<f32>) -> vec4<f32> {
+    let luma = dot(color.xyz, vec3<f32>(0.2126, 0.7152, 0.0722));
+    return vec4<f32>(luma, 0.0, 0.0, color.w);
+}
+
+// Position of channel `c` of pixel (x, y) in the flattened tensor
+fn tensor_index(x: u32, y: u32, c: u32) -> u32 {
+    let pixel = y * params.output_width + x;
+    if (params.channels_last == 1u) {
+        return pixel * params.channels + c;
+    }
+    return c * params.output_width * params.output_height + pixel;
+}
+
+// Store one tensor element into its half of a packed f16 pair
+fn store_packed(index: u32, value: f32) {
+    let bits = pack2x16float(vec2<f32>(value, 0.0)) << (16u * (index & 1u));
+    atomicOr(&packed[index >> 1u], bits);
+}
+
+// Quantize like QuantParams::quantize, rounding halfway cases away from zero
+fn quantize(value: f32) -> i32 {
+    let x = value / params.quant_scale;
+    let t = trunc(x);
+    let rounded = t + sign(x) * step(0.5, abs(x - t));
+    let q = clamp(rounded + f32(params.zero_point), f32(params.quant_min), f32(params.quant_max));
+    return i32(q);
+}
+
+// Store one quantized tensor element into its byte of a packed quad
+fn store_quantized(index: u32, value: f32) {
+    let bits = (bitcast<u32>(quantize(value)) & 0xffu) << (8u * (index & 3u));
+    atomicOr(&packed[index >> 2u], bits);
+}
+
 // Bicubic Catmull-Rom interpolation
 fn bicubic_catmull_rom(pos: vec2<f32>) -> vec4<f32> {
     // Calculate the integer coordinates and fractional offsets
@@ -100,27 +158,143 @@ fn bicubic_catmull_rom(pos: vec2<f32>) -> vec4<f32> {
     return catmull_rom_1d(row0, row1, row2, row3, fy);
 }
 
+// Nearest neighbor, the input pixel containing `pos`
+fn nearest(pos: vec2<f32>) -> vec4<f32> {
+    let p = vec2<i32>(floor(pos));
+    return safe_sample(p.x, p.y);
+}
+
+// Bilinear interpolation between the four pixel centers around `pos`
+fn bilinear(pos: vec2<f32>) -> vec4<f32> {
+    let centered = pos - 0.5;
+    let p = vec2<i32>(floor(centered));
+    let t = centered - floor(centered);
+    let top = mix(safe_sample(p.x, p.y), safe_sample(p.x + 1, p.y), t.x);
+    let bottom = mix(safe_sample(p.x, p.y + 1), safe_sample(p.x + 1, p.y + 1), t.x);
+    return mix(top, bottom, t.y);
+}
+
+// image's Gaussian (sigma 0.5) or Lanczos3 kernel, both three pixels wide
+fn wide_kernel(x: f32) -> f32 {
+    if (params.interpolation == 3u) {
+        return exp(-2.0 * x * x);
+    }
+    if (abs(x) >= 3.0) {
+        return 0.0;
+    }
+    if (x == 0.0) {
+        return 1.0;
+    }
+    // sinc(x) * sinc(x / 3)
+    let a = 3.14159265 * x;
+    return 3.0 * sin(a) * sin(a / 3.0) / (a * a);
+}
+
+// Weighted sum of the 6x6 pixels around `pos` with `wide_kernel`. Like
+// image, pixels outside the input are left out rather than clamped
+fn wide(pos: vec2<f32>) -> vec4<f32> {
+    let center = pos - 0.5;
+    let first = vec2<i32>(floor(center)) - 2;
+    var color = vec4<f32>(0.0);
+    var total = 0.0;
+    for (var j = 0; j < 6; j++) {
+        let y = first.y + j;
+        if (y < 0 || y >= i32(params.input_height)) {
+            continue;
+        }
+        let wy = wide_kernel(f32(y) - center.y);
+        for (var i = 0; i < 6; i++) {
+            let x = first.x + i;
+            if (x < 0 || x >= i32(params.input_width)) {
+                continue;
+            }
+            let w = wy * wide_kernel(f32(x) - center.x);
+            color += w * textureLoad(input_texture, vec2<i32>(x, y), 0);
+            total += w;
+        }
+    }
+    return color / total;
+}
+
+// Interpolate the input at `pos` with the configured filter
+fn interpolate(pos: vec2<f32>) -> vec4<f32> {
+    switch params.interpolation {
+        case 0u: {
+            return nearest(pos);
+        }
+        case 1u: {
+            return bilinear(pos);
+        }
+        case 2u: {
+            return bicubic_catmull_rom(pos);
+        }
+        default: {
+            return wide(pos);
+        }
+    }
+}
+
+// The normalized color of output pixel `id`
+fn pixel(id: vec2<u32>) -> vec4<f32> {
+    // Calculate the sample position in the input texture
+    let scale_x = f32(params.input_width) / f32(params.resize_width);
+    let scale_y = f32(params.input_height) / f32(params.resize_height);
+
+    // Offset into the central crop of the resized image
+    let resized_x = i32(id.x) + params.crop_x;
+    let resized_y = i32(id.y) + params.crop_y;
+
+    // Pad with the fill color where the crop extends past the resized image
+    var color = params.fill;
+    if (resized_x >= 0 && resized_x < i32(params.resize_width)
+        && resized_y >= 0 && resized_y < i32(params.resize_height)) {
+        // Add 0.5 to sample from pixel centers
+        let input_pos = vec2<f32>(
+            (f32(resized_x) + 0.5) * scale_x,
+            (f32(resized_y) + 0.5) * scale_y
+        );
+
+        color = interpolate(input_pos);
+    }
+    if (params.channels == 1u) {
+        color = to_luma(color);
+    }
+    return normalize(prepare(color), params.mean, params.avg);
+}
+
 @compute @workgroup_size(16, 16, 1)
 fn main(@builtin(global_invocation_id) global_id: vec3<u32>) {
     // Check if within output bounds
     if (global_id.x >= params.output_width || global_id.y >= params.output_height) {
         return;
     }
+    let normalized = pixel(global_id.xy);
+    // Write the first `channels` values to the output tensor
+    for (var c = 0u; c < params.channels; c++) {
+        output[tensor_index(global_id.x, global_id.y, c)] = normalized[c];
+    }
+}
 
-    // Calculate the sample position in the input texture
-    let scale_x = f32(params.input_width) / f32(params.output_width);
-    let scale_y = f32(params.input_height) / f32(params.output_height);
-
-    // Add 0.5 to sample from pixel centers
-    let input_pos = vec2<f32>(
-        (f32(global_id.x) + 0.5) * scale_x,
-        (f32(global_id.y) + 0.5) * scale_y
-    );
-
-    // Get the interpolated color using Catmull-Rom
-    let color = bicubic_catmull_rom(input_pos);
-    let normalized = normalize(color, params.mean, params.avg);
-    // Write the result to the output texture
-    textureStore(output_texture, vec2<i32>(global_id.xy), normalized);
-    //textureStore(output_texture, vec2<i32>(global_id.xy), color);
+// Like `main`, but writes f16 values packed in pairs into a zeroed buffer
+@compute @workgroup_size(16, 16, 1)
+fn main_packed(@builtin(global_invocation_id) global_id: vec3<u32>) {
+    if (global_id.x >= params.output_width || global_id.y >= params.output_height) {
+        return;
+    }
+    let normalized = pixel(global_id.xy);
+    for (var c = 0u; c < params.channels; c++) {
+        store_packed(tensor_index(global_id.x, global_id.y, c), normalized[c]);
+    }
+}
+
+// Like `main`, but writes quantized 8-bit values packed in quads into a zeroed buffer
+@compute @workgroup_size(16, 16, 1)
+fn main_quantized(@builtin(global_invocation_id) global_id: vec3<u32>) {
+    if (global_id.x >= params.output_width || global_id.y >= params.output_height) {
+        return;
+    }
+    let normalized = pixel(global_id.xy);
+    for (var c = 0u; c < params.channels; c++) {
+        store_quantized(tensor_index(global_id.x, global_id.y, c), normalized[c]);
+    }
 }
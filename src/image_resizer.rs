@@ -1,9 +1,11 @@
-use std::num::NonZeroU32;
+use std::{num::NonZeroU32, sync::Arc};
 
-use anyhow::Ok;
 use image::{DynamicImage, GenericImageView, ImageBuffer, Rgba};
-use wgpu::{
-    BindGroupLayout, ComputePipeline, Device, Queue, ShaderModule, include_wgsl, util::DeviceExt,
+use wgpu::{BindGroupLayout, ComputePipeline, Device, Queue, ShaderModule, include_wgsl};
+
+use crate::{
+    error::{Result, TensorizeError},
+    gpu_context::{GpuContext, GpuOptions},
 };
 
 #[repr(C)]
@@ -16,8 +18,7 @@ struct ResizeParams {
 }
 
 pub struct ImageResizer {
-    device: Device,
-    queue: Queue,
+    context: GpuContext,
     compute_pipeline: ComputePipeline,
     bind_group_layout: BindGroupLayout,
     output_width: u32,
@@ -29,25 +30,46 @@ fn create_catmull_rom_shader(device: &wgpu::Device) -> ShaderModule {
 }
 
 impl ImageResizer {
-    pub async fn new(output_width: u32, output_height: u32) -> anyhow::Result<Self> {
-        let instance = wgpu::Instance::new(&wgpu::InstanceDescriptor::default());
-        let adapter = instance
-            .request_adapter(&wgpu::RequestAdapterOptions {
-                power_preference: wgpu::PowerPreference::default(),
-                compatible_surface: None,
-                force_fallback_adapter: false,
-            })
-            .await?;
+    pub async fn new(output_width: u32, output_height: u32) -> Result<Self> {
+        let context = GpuContext::new().await?;
+        Ok(ImageResizer::with_context(
+            &context,
+            output_width,
+            output_height,
+        ))
+    }
 
-        let (device, queue) = adapter
-            .request_device(&wgpu::DeviceDescriptor {
-                label: None,
-                required_features: wgpu::Features::empty(),
-                required_limits: wgpu::Limits::default(),
-                memory_hints: wgpu::MemoryHints::Performance,
-                trace: wgpu::Trace::default(),
-            })
-            .await?;
+    /// Creates a resizer on the adapter described by `options`.
+    pub async fn with_options(
+        output_width: u32,
+        output_height: u32,
+        options: GpuOptions,
+    ) -> Result<Self> {
+        let context = GpuContext::with_options(options).await?;
+        Ok(ImageResizer::with_context(
+            &context,
+            output_width,
+            output_height,
+        ))
+    }
+
+    /// Creates a resizer on an existing device. See [`GpuContext::from_device`].
+    pub fn from_device(
+        device: Arc<Device>,
+        queue: Arc<Queue>,
+        output_width: u32,
+        output_height: u32,
+    ) -> Self {
+        ImageResizer::with_context(
+            &GpuContext::from_device(device, queue),
+            output_width,
+            output_height,
+        )
+    }
+
+    /// Creates a resizer on the device of `context`.
+    pub fn with_context(context: &GpuContext, output_width: u32, output_height: u32) -> Self {
+        let device = context.device();
 
         // Create textures for input and output
 
@@ -94,7 +116,7 @@ impl ImageResizer {
             bind_group_layouts: &[&bind_group_layout],
             push_constant_ranges: &[],
         });
-        let shader = create_catmull_rom_shader(&device);
+        let shader = create_catmull_rom_shader(device);
 
         let compute_pipeline = device.create_compute_pipeline(&wgpu::ComputePipelineDescriptor {
             label: Some("Resize Compute Pipeline"),
@@ -105,18 +127,77 @@ impl ImageResizer {
             cache: None,
         });
 
-        Ok(ImageResizer {
-            device,
-            queue,
+        ImageResizer {
+            context: context.clone(),
             bind_group_layout,
             compute_pipeline,
             output_width,
             output_height,
-        })
+        }
+    }
+
+    /// The context this resizer runs on.
+    pub fn context(&self) -> &GpuContext {
+        &self.context
     }
 
-    pub async fn rescale(&self, img: &DynamicImage, output_path: &str) -> anyhow::Result<()> {
+    /// Describes the adapter in use, unless it was created from an existing device.
+    pub fn adapter_info(&self) -> Option<wgpu::AdapterInfo> {
+        self.context.adapter_info()
+    }
+
+    /// Resizes `img` and saves the result to `output_path`, inferring the
+    /// format from its extension.
+    pub async fn rescale(&self, img: &DynamicImage, output_path: &str) -> Result<()> {
+        let output_image = self.resize(img).await?;
+        output_image.save(output_path)?;
+        log::debug!("Image resized and saved to {output_path}");
+        Ok(())
+    }
+
+    /// Resizes `img` to the output size of this resizer.
+    pub async fn resize(&self, img: &DynamicImage) -> Result<DynamicImage> {
+        let mut images = self
+            .resize_to_sizes(img, &[(self.output_width, self.output_height)])
+            .await?;
+        Ok(images.remove(0))
+    }
+
+    /// Resizes `img` to every `(width, height)` in `sizes`.
+    ///
+    /// The input is uploaded once and all sizes are computed in a single
+    /// submission. Images are returned in the order of `sizes`.
+    pub async fn resize_to_sizes(
+        &self,
+        img: &DynamicImage,
+        sizes: &[(u32, u32)],
+    ) -> Result<Vec<DynamicImage>> {
+        self.context.detect_loss()?;
         let (input_width, input_height) = img.dimensions();
+        if input_width == 0 || input_height == 0 {
+            return Err(TensorizeError::EmptyImage);
+        }
+        let max = self.context.device().limits().max_texture_dimension_2d;
+        if input_width > max || input_height > max {
+            return Err(TensorizeError::ImageTooLarge {
+                width: input_width,
+                height: input_height,
+                max,
+            });
+        }
+        for &(width, height) in sizes {
+            if width == 0 || height == 0 {
+                return Err(TensorizeError::InvalidConfig(format!(
+                    "output size {width}x{height} must be non-zero"
+                )));
+            }
+            if width > max || height > max {
+                return Err(TensorizeError::ImageTooLarge { width, height, max });
+            }
+        }
+        if sizes.is_empty() {
+            return Ok(Vec::new());
+        }
         let rgba_img = img.to_rgba8();
         let img_data = rgba_img.into_raw();
         // Create textures for input and output
@@ -126,37 +207,22 @@ impl ImageResizer {
             depth_or_array_layers: 1,
         };
 
-        let input_texture = self.device.create_texture(&wgpu::TextureDescriptor {
-            label: Some("Input Texture"),
-            size: texture_size,
-            mip_level_count: 1,
-            sample_count: 1,
-            dimension: wgpu::TextureDimension::D2,
-            format: wgpu::TextureFormat::Rgba8Unorm,
-            usage: wgpu::TextureUsages::TEXTURE_BINDING | wgpu::TextureUsages::COPY_DST,
-            view_formats: &[],
-        });
-
-        // Create output texture
-        let output_texture_size = wgpu::Extent3d {
-            width: self.output_width,
-            height: self.output_height,
-            depth_or_array_layers: 1,
-        };
-
-        let output_texture = self.device.create_texture(&wgpu::TextureDescriptor {
-            label: Some("Output Texture"),
-            size: output_texture_size,
-            mip_level_count: 1,
-            sample_count: 1,
-            dimension: wgpu::TextureDimension::D2,
-            format: wgpu::TextureFormat::Rgba8Unorm,
-            usage: wgpu::TextureUsages::STORAGE_BINDING | wgpu::TextureUsages::COPY_SRC,
-            view_formats: &[],
-        });
+        let input_texture = self
+            .context
+            .device()
+            .create_texture(&wgpu::TextureDescriptor {
+                label: Some("Input Texture"),
+                size: texture_size,
+                mip_level_count: 1,
+                sample_count: 1,
+                dimension: wgpu::TextureDimension::D2,
+                format: wgpu::TextureFormat::Rgba8Unorm,
+                usage: wgpu::TextureUsages::TEXTURE_BINDING | wgpu::TextureUsages::COPY_DST,
+                view_formats: &[],
+            });
 
         // Upload image data to the input texture
-        self.queue.write_texture(
+        self.context.queue().write_texture(
             wgpu::TexelCopyTextureInfo {
                 texture: &input_texture,
                 mip_level: 0,
@@ -171,141 +237,178 @@ impl ImageResizer {
             },
             texture_size,
         );
+        let input_view = input_texture.create_view(&wgpu::TextureViewDescriptor::default());
 
-        // Create the resize parameters buffer
-        let resize_params = ResizeParams {
-            input_width,
-            input_height,
-            output_width: self.output_width,
-            output_height: self.output_height,
-        };
-
-        let resize_params_buffer =
-            self.device
-                .create_buffer_init(&wgpu::util::BufferInitDescriptor {
-                    label: Some("Resize Parameters Buffer"),
-                    contents: bytemuck::cast_slice(&[resize_params]),
-                    usage: wgpu::BufferUsages::UNIFORM,
-                });
-
-        // Create bind group
-        let bind_group = self.device.create_bind_group(&wgpu::BindGroupDescriptor {
-            label: Some("Resize Bind Group"),
-            layout: &self.bind_group_layout,
-            entries: &[
-                wgpu::BindGroupEntry {
-                    binding: 0,
-                    resource: wgpu::BindingResource::TextureView(
-                        &input_texture.create_view(&wgpu::TextureViewDescriptor::default()),
-                    ),
-                },
-                wgpu::BindGroupEntry {
-                    binding: 1,
-                    resource: wgpu::BindingResource::TextureView(
-                        &output_texture.create_view(&wgpu::TextureViewDescriptor::default()),
-                    ),
-                },
-                wgpu::BindGroupEntry {
-                    binding: 2,
-                    resource: resize_params_buffer.as_entire_binding(),
-                },
-            ],
-        });
+        // Calculate bytes_per_row with proper alignment (256 bytes) for every size.
+        // Each result gets its own slot in one output buffer.
+        let align = 256;
+        let bytes_per_pixel = 4; // RGBA8 = 4 bytes per pixel
+        let mut layouts = Vec::with_capacity(sizes.len());
+        let mut output_buffer_size = 0;
+        for &(width, height) in sizes {
+            let unpadded_bytes_per_row = width * bytes_per_pixel;
+            let padding = (align - unpadded_bytes_per_row % align) % align;
+            let padded_bytes_per_row = unpadded_bytes_per_row + padding;
+            layouts.push((output_buffer_size, padded_bytes_per_row));
+            output_buffer_size += padded_bytes_per_row as u64 * height as u64;
+        }
 
-        // Execute the compute shader
-        let mut encoder = self
-            .device
-            .create_command_encoder(&wgpu::CommandEncoderDescriptor {
-                label: Some("Resize Command Encoder"),
+        // Create output buffer to retrieve the resized image data
+        let output_buffer = self
+            .context
+            .device()
+            .create_buffer(&wgpu::BufferDescriptor {
+                label: Some("Output Buffer"),
+                size: output_buffer_size,
+                usage: wgpu::BufferUsages::COPY_DST | wgpu::BufferUsages::MAP_READ,
+                mapped_at_creation: false,
             });
 
-        {
-            let mut compute_pass = encoder.begin_compute_pass(&wgpu::ComputePassDescriptor {
-                label: Some("Resize Compute Pass"),
-                timestamp_writes: None,
-            });
-            compute_pass.set_pipeline(&self.compute_pipeline);
-            compute_pass.set_bind_group(0, &bind_group, &[]);
-            compute_pass.dispatch_workgroups(
-                (self.output_width + 15) / 16,
-                (self.output_height + 15) / 16,
-                1,
-            );
-        }
+        let mut encoder =
+            self.context
+                .device()
+                .create_command_encoder(&wgpu::CommandEncoderDescriptor {
+                    label: Some("Resize Command Encoder"),
+                });
 
-        // Calculate bytes_per_row with proper alignment (256 bytes)
-        let align = 256;
-        let bytes_per_pixel = 4; // RGBA8 = 4 bytes per pixel
-        let unpadded_bytes_per_row = self.output_width * bytes_per_pixel;
-        let padding = (align - unpadded_bytes_per_row % align) % align;
-        let padded_bytes_per_row = unpadded_bytes_per_row + padding;
+        for (&(output_width, output_height), &(offset, padded_bytes_per_row)) in
+            sizes.iter().zip(&layouts)
+        {
+            // Create output texture
+            let output_texture_size = wgpu::Extent3d {
+                width: output_width,
+                height: output_height,
+                depth_or_array_layers: 1,
+            };
+
+            let output_texture = self
+                .context
+                .device()
+                .create_texture(&wgpu::TextureDescriptor {
+                    label: Some("Output Texture"),
+                    size: output_texture_size,
+                    mip_level_count: 1,
+                    sample_count: 1,
+                    dimension: wgpu::TextureDimension::D2,
+                    format: wgpu::TextureFormat::Rgba8Unorm,
+                    usage: wgpu::TextureUsages::STORAGE_BINDING | wgpu::TextureUsages::COPY_SRC,
+                    view_formats: &[],
+                });
 
-        // Create output buffer to retrieve the resized image data
-        let output_buffer_size = padded_bytes_per_row as u64 * self.output_height as u64;
-        let output_buffer = self.device.create_buffer(&wgpu::BufferDescriptor {
-            label: Some("Output Buffer"),
-            size: output_buffer_size,
-            usage: wgpu::BufferUsages::COPY_DST | wgpu::BufferUsages::MAP_READ,
-            mapped_at_creation: false,
-        });
+            // Create the resize parameters buffer
+            let resize_params = ResizeParams {
+                input_width,
+                input_height,
+                output_width,
+                output_height,
+            };
+
+            let resize_params_buffer = self
+                .context
+                .uniform_buffer("Resize Parameters Buffer", &resize_params);
+
+            // Create bind group
+            let bind_group = self
+                .context
+                .device()
+                .create_bind_group(&wgpu::BindGroupDescriptor {
+                    label: Some("Resize Bind Group"),
+                    layout: &self.bind_group_layout,
+                    entries: &[
+                        wgpu::BindGroupEntry {
+                            binding: 0,
+                            resource: wgpu::BindingResource::TextureView(&input_view),
+                        },
+                        wgpu::BindGroupEntry {
+                            binding: 1,
+                            resource: wgpu::BindingResource::TextureView(
+                                &output_texture
+                                    .create_view(&wgpu::TextureViewDescriptor::default()),
+                            ),
+                        },
+                        wgpu::BindGroupEntry {
+                            binding: 2,
+                            resource: resize_params_buffer.as_entire_binding(),
+                        },
+                    ],
+                });
 
-        // Copy the output texture to the output buffer
-        encoder.copy_texture_to_buffer(
-            wgpu::TexelCopyTextureInfo {
-                texture: &output_texture,
-                mip_level: 0,
-                origin: wgpu::Origin3d::ZERO,
-                aspect: wgpu::TextureAspect::All,
-            },
-            wgpu::TexelCopyBufferInfo {
-                buffer: &output_buffer,
-                layout: wgpu::TexelCopyBufferLayout {
-                    offset: 0,
-                    bytes_per_row: Some(NonZeroU32::new(padded_bytes_per_row).unwrap().into()),
-                    rows_per_image: Some(NonZeroU32::new(self.output_height).unwrap().into()),
+            // Execute the compute shader
+            {
+                let mut compute_pass = encoder.begin_compute_pass(&wgpu::ComputePassDescriptor {
+                    label: Some("Resize Compute Pass"),
+                    timestamp_writes: None,
+                });
+                compute_pass.set_pipeline(&self.compute_pipeline);
+                compute_pass.set_bind_group(0, &bind_group, &[]);
+                compute_pass.dispatch_workgroups(
+                    output_width.div_ceil(16),
+                    output_height.div_ceil(16),
+                    1,
+                );
+            }
+
+            // Copy the output texture to its slot in the output buffer
+            encoder.copy_texture_to_buffer(
+                wgpu::TexelCopyTextureInfo {
+                    texture: &output_texture,
+                    mip_level: 0,
+                    origin: wgpu::Origin3d::ZERO,
+                    aspect: wgpu::TextureAspect::All,
                 },
-            },
-            output_texture_size,
-        );
+                wgpu::TexelCopyBufferInfo {
+                    buffer: &output_buffer,
+                    layout: wgpu::TexelCopyBufferLayout {
+                        offset,
+                        bytes_per_row: Some(NonZeroU32::new(padded_bytes_per_row).unwrap().into()),
+                        rows_per_image: Some(NonZeroU32::new(output_height).unwrap().into()),
+                    },
+                },
+                output_texture_size,
+            );
+        }
 
-        self.queue.submit(std::iter::once(encoder.finish()));
+        // A failed submission would read back an empty buffer
+        self.context.scoped(|| {
+            self.context
+                .queue()
+                .submit(std::iter::once(encoder.finish()))
+        })?;
 
         // Read back the output buffer
         let buffer_slice = output_buffer.slice(..);
 
-        buffer_slice.map_async(wgpu::MapMode::Read, move |_| {});
-
-        let _ = self.device.poll(wgpu::PollType::Wait);
+        let mapped = self.context.map_read(buffer_slice).await;
+        if mapped.is_err() {
+            self.context.detect_loss()?;
+        }
+        mapped?;
 
         let data = buffer_slice.get_mapped_range();
 
-        // Create a new vector to unpad the rows
-        let mut result_rgba =
-            Vec::with_capacity((self.output_width * self.output_height * 4) as usize);
-
-        // Copy each row, removing padding
-        for y in 0..self.output_height {
-            let row_start = y as usize * padded_bytes_per_row as usize;
-            let row_end = row_start + (self.output_width as usize * 4);
-            result_rgba.extend_from_slice(&data.as_ref()[row_start..row_end]);
+        let mut images = Vec::with_capacity(sizes.len());
+        for (&(output_width, output_height), &(offset, padded_bytes_per_row)) in
+            sizes.iter().zip(&layouts)
+        {
+            // Create a new vector to unpad the rows
+            let mut result_rgba = Vec::with_capacity((output_width * output_height * 4) as usize);
+
+            // Copy each row, removing padding
+            for y in 0..output_height {
+                let row_start = offset as usize + y as usize * padded_bytes_per_row as usize;
+                let row_end = row_start + (output_width as usize * 4);
+                result_rgba.extend_from_slice(&data.as_ref()[row_start..row_end]);
+            }
+
+            let output_image =
+                ImageBuffer::<Rgba<u8>, _>::from_raw(output_width, output_height, result_rgba)
+                    .unwrap();
+            images.push(DynamicImage::ImageRgba8(output_image));
         }
 
         drop(data);
         output_buffer.unmap();
 
-        // Save the output image
-        let output_image = ImageBuffer::<Rgba<u8>, _>::from_raw(
-            self.output_width,
-            self.output_height,
-            result_rgba,
-        )
-        .unwrap();
-
-        output_image.save(output_path)?;
-        println!("Image resized and saved to {}", output_path);
-
-        //            Ok(())
-
-        Ok(())
+        Ok(images)
     }
 }
@@ -0,0 +1,125 @@
+//! Mapping between input image and tensor coordinates.
+
+/// Where an input image ended up inside the tensor.
+///
+/// The input is scaled to `resized_width` x `resized_height` and placed with
+/// its top-left corner at (`pad_x`, `pad_y`) in the `width` x `height` tensor.
+/// Positive offsets are padding, negative ones mean the image was cropped.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct LetterboxInfo {
+    pub input_width: u32,
+    pub input_height: u32,
+    pub resized_width: u32,
+    pub resized_height: u32,
+    pub width: u32,
+    pub height: u32,
+    pub pad_x: i32,
+    pub pad_y: i32,
+}
+
+impl LetterboxInfo {
+    /// Horizontal scale from input to tensor pixels.
+    pub fn scale_x(&self) -> f32 {
+        self.resized_width as f32 / self.input_width as f32
+    }
+
+    /// Vertical scale from input to tensor pixels.
+    pub fn scale_y(&self) -> f32 {
+        self.resized_height as f32 / self.input_height as f32
+    }
+
+    /// Maps a point in input image coordinates into tensor coordinates.
+    pub fn map_point(&self, x: f32, y: f32) -> (f32, f32) {
+        (
+            x * self.scale_x() + self.pad_x as f32,
+            y * self.scale_y() + self.pad_y as f32,
+        )
+    }
+
+    /// Maps a point in tensor coordinates back into input image coordinates.
+    pub fn map_point_back(&self, x: f32, y: f32) -> (f32, f32) {
+        (
+            (x - self.pad_x as f32) / self.scale_x(),
+            (y - self.pad_y as f32) / self.scale_y(),
+        )
+    }
+
+    /// Maps an `[x1, y1, x2, y2]` box predicted on the tensor back onto the
+    /// input image, clipped to the image bounds.
+    pub fn map_box_back(&self, [x1, y1, x2, y2]: [f32; 4]) -> [f32; 4] {
+        let (x1, y1) = self.map_point_back(x1, y1);
+        let (x2, y2) = self.map_point_back(x2, y2);
+        let (w, h) = (self.input_width as f32, self.input_height as f32);
+        [
+            x1.clamp(0.0, w),
+            y1.clamp(0.0, h),
+            x2.clamp(0.0, w),
+            y2.clamp(0.0, h),
+        ]
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::cpu_tensor::{ImageConvert, ResizeMode};
+
+    fn letterbox(size: u32, stride: Option<u32>) -> ImageConvert {
+        ImageConvert::builder()
+            .resize(size, size)
+            .resize_mode(ResizeMode::Letterbox { stride })
+            .build()
+            .unwrap()
+    }
+
+    #[test]
+    fn matches_ultralytics_letterbox() {
+        // LetterBox(640, auto=True, stride=32) on a 271x400 image: the scaled
+        // 434x640 image is only padded to the next multiple of 32
+        let info = letterbox(640, Some(32)).letterbox_info(271, 400);
+        assert_eq!((info.resized_width, info.resized_height), (434, 640));
+        assert_eq!((info.width, info.height), (448, 640));
+        assert_eq!((info.pad_x, info.pad_y), (7, 0));
+
+        // auto=False pads to the full square, both sides of the scale are rounded
+        let info = letterbox(640, None).letterbox_info(271, 400);
+        assert_eq!((info.width, info.height), (640, 640));
+        assert_eq!((info.pad_x, info.pad_y), (103, 0));
+        let info = letterbox(640, None).letterbox_info(1280, 720);
+        assert_eq!((info.resized_width, info.resized_height), (640, 360));
+        assert_eq!(info.pad_y, 140);
+    }
+
+    #[test]
+    fn odd_padding_goes_right_and_bottom() {
+        // 213 rows of padding: ultralytics puts round(106.5 - 0.1) = 106 on top
+        let info = letterbox(640, None).letterbox_info(640, 427);
+        assert_eq!((info.height, info.pad_y), (640, 106));
+        // With stride 32 the 21 remaining rows split 10 / 11
+        let info = letterbox(640, Some(32)).letterbox_info(640, 427);
+        assert_eq!((info.height, info.pad_y), (448, 10));
+    }
+
+    #[test]
+    fn map_box_back_inverts_the_placement() {
+        let info = letterbox(640, Some(32)).letterbox_info(271, 400);
+        // The placed image maps exactly onto the input
+        assert_eq!(
+            info.map_box_back([7.0, 0.0, 441.0, 640.0]),
+            [0.0, 0.0, 271.0, 400.0]
+        );
+        // Boxes reaching into the padding are clipped to the image
+        assert_eq!(
+            info.map_box_back([-20.0, -5.0, 460.0, 700.0]),
+            [0.0, 0.0, 271.0, 400.0]
+        );
+        // ultralytics' scale_boxes divides by the gain 1.6 after removing the
+        // padding, which differs from the exact 434 / 271 by a fraction of a pixel
+        let mapped = info.map_box_back([167.0, 160.0, 327.0, 480.0]);
+        for (mapped, expected) in mapped.into_iter().zip([100.0, 100.0, 200.0, 300.0]) {
+            assert!((mapped - expected).abs() < 0.25, "{mapped} != {expected}");
+        }
+        let (x, y) = info.map_point(120.5, 33.0);
+        let (x, y) = info.map_point_back(x, y);
+        assert!((x - 120.5).abs() < 1e-3 && (y - 33.0).abs() < 1e-3);
+    }
+}
@@ -1,11 +1,27 @@
+pub use auto_tensor::{AutoTensorizer, Backend};
 pub use cpu_tensor::{
-    CpuTensorizer, IMAGENET_DEFAULT_CONFIG, IMAGENET_DEFAULT_CONFIG_NO_CROP, IMAGENET_DEFAULT_MEAN,
-    IMAGENET_DEFAULT_STD,
+    ChannelOrder, CpuTensorizer, IMAGENET_DEFAULT_CONFIG, IMAGENET_DEFAULT_CONFIG_NO_CROP,
+    IMAGENET_DEFAULT_MEAN, IMAGENET_DEFAULT_STD, ImageConvert, ImageConvertBuilder,
+    LETTERBOX_DEFAULT_CONFIG, Layout, ResizeMode,
 };
-pub use gpu_tensor::GpuTensorizer;
+pub use element::{QuantElement, QuantParams, TensorElement};
+pub use error::TensorizeError;
+pub use gpu_context::{AdapterSelector, GpuContext, GpuOptions};
+pub use gpu_tensor::{DType, GpuTensor, GpuTensorizer, RecoveryPolicy};
+pub use half::{bf16, f16};
 pub use image_resizer::ImageResizer;
+pub use letterbox::LetterboxInfo;
 pub use tensorizer_trait::Tensorizer;
+pub mod auto_tensor;
 pub mod cpu_tensor;
+pub mod element;
+pub mod error;
+pub mod gpu_context;
 pub mod gpu_tensor;
+pub mod hf_config;
 pub mod image_resizer;
+pub mod letterbox;
+mod poller;
+pub mod presets;
 pub mod tensorizer_trait;
+pub mod timm_config;
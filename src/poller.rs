@@ -0,0 +1,42 @@
+//! Asynchronous buffer readback.
+
+use std::sync::mpsc;
+
+use wgpu::{BufferAsyncError, BufferSlice, Device};
+
+/// Drives a device from a dedicated thread so that awaiting a buffer map
+/// never blocks the async runtime.
+///
+/// The thread exits once the poller is dropped.
+pub(crate) struct Poller {
+    wake: mpsc::Sender<()>,
+}
+
+impl Poller {
+    pub(crate) fn new(device: Device) -> Self {
+        let (wake, requests) = mpsc::channel::<()>();
+        std::thread::Builder::new()
+            .name("tensorize-poll".to_string())
+            .spawn(move || {
+                while requests.recv().is_ok() {
+                    // Wait until every submission so far has finished, which
+                    // also runs the pending map callbacks
+                    while let Err(err) = device.poll(wgpu::PollType::Wait) {
+                        log::warn!("polling the device failed, retrying: {err}");
+                    }
+                }
+            })
+            .expect("failed to spawn the device poll thread");
+        Poller { wake }
+    }
+
+    /// Maps `slice` for reading and waits for the map callback.
+    pub(crate) async fn map_read(&self, slice: BufferSlice<'_>) -> Result<(), BufferAsyncError> {
+        let (sender, receiver) = tokio::sync::oneshot::channel();
+        slice.map_async(wgpu::MapMode::Read, move |result| {
+            let _ = sender.send(result);
+        });
+        self.wake.send(()).map_err(|_| BufferAsyncError)?;
+        receiver.await.unwrap_or(Err(BufferAsyncError))
+    }
+}
@@ -0,0 +1,323 @@
+//! Preprocessing presets for common model families.
+//!
+//! Each preset mirrors the evaluation transform published with the reference
+//! weights (the Hugging Face `preprocessor_config.json`, timm `pretrained_cfg`
+//! or torchvision weights transform named in its doc comment).
+
+use image::imageops::FilterType;
+
+use crate::cpu_tensor::{
+    ChannelOrder, IMAGENET_DEFAULT_CONFIG, IMAGENET_DEFAULT_MEAN, IMAGENET_DEFAULT_STD,
+    ImageConvert, LETTERBOX_DEFAULT_CONFIG, Layout, ResizeMode,
+};
+
+pub const OPENAI_CLIP_MEAN: [f32; 3] = [0.48145466, 0.4578275, 0.40821073];
+pub const OPENAI_CLIP_STD: [f32; 3] = [0.26862954, 0.2613026, 0.2757771];
+/// Maps `[0, 1]` onto `[-1, 1]`.
+pub const HALF_MEAN: [f32; 3] = [0.5, 0.5, 0.5];
+pub const HALF_STD: [f32; 3] = [0.5, 0.5, 0.5];
+/// Caffe ImageNet mean in BGR order and 0..255 space, used with an input scale of 1.
+pub const CAFFE_BGR_MEAN: [f32; 3] = [103.939, 116.779, 123.68];
+
+const fn square(
+    resize_mode: ResizeMode,
+    resize: u32,
+    crop: u32,
+    mean: [f32; 3],
+    std: [f32; 3],
+    interpolation: FilterType,
+) -> ImageConvert {
+    ImageConvert {
+        channels: 3,
+        width: resize,
+        height: resize,
+        crop_width: crop,
+        crop_height: crop,
+        mean,
+        std,
+        interpolation,
+        resize_mode,
+        fill: [0; 3],
+        layout: Layout::Chw,
+        channel_order: ChannelOrder::Rgb,
+        input_scale: 1.0 / 255.0,
+    }
+}
+
+/// Resize 256, center crop 224, ImageNet mean/std.
+pub const fn imagenet() -> ImageConvert {
+    IMAGENET_DEFAULT_CONFIG
+}
+
+/// `openai/clip-vit-base-patch32`: bicubic shortest edge 224, center crop 224, OpenAI mean/std.
+pub const fn clip() -> ImageConvert {
+    square(
+        ResizeMode::ShortestEdge,
+        224,
+        224,
+        OPENAI_CLIP_MEAN,
+        OPENAI_CLIP_STD,
+        FilterType::CatmullRom,
+    )
+}
+
+/// `openai/clip-vit-large-patch14-336`: bicubic shortest edge 336, center crop 336, OpenAI mean/std.
+pub const fn clip_336() -> ImageConvert {
+    square(
+        ResizeMode::ShortestEdge,
+        336,
+        336,
+        OPENAI_CLIP_MEAN,
+        OPENAI_CLIP_STD,
+        FilterType::CatmullRom,
+    )
+}
+
+/// `google/siglip-base-patch16-224`: bicubic squash to 224, mean/std 0.5.
+pub const fn siglip_224() -> ImageConvert {
+    square(
+        ResizeMode::Exact,
+        224,
+        224,
+        HALF_MEAN,
+        HALF_STD,
+        FilterType::CatmullRom,
+    )
+}
+
+/// `google/siglip-base-patch16-384`: bicubic squash to 384, mean/std 0.5.
+pub const fn siglip_384() -> ImageConvert {
+    square(
+        ResizeMode::Exact,
+        384,
+        384,
+        HALF_MEAN,
+        HALF_STD,
+        FilterType::CatmullRom,
+    )
+}
+
+/// `facebook/dinov2-base`: bicubic shortest edge 256, center crop 224, ImageNet mean/std.
+pub const fn dinov2() -> ImageConvert {
+    square(
+        ResizeMode::ShortestEdge,
+        256,
+        224,
+        IMAGENET_DEFAULT_MEAN,
+        IMAGENET_DEFAULT_STD,
+        FilterType::CatmullRom,
+    )
+}
+
+/// `facebook/convnext-base-224`: bicubic shortest edge 256 (crop_pct 0.875), center crop 224,
+/// ImageNet mean/std.
+pub const fn convnext() -> ImageConvert {
+    square(
+        ResizeMode::ShortestEdge,
+        256,
+        224,
+        IMAGENET_DEFAULT_MEAN,
+        IMAGENET_DEFAULT_STD,
+        FilterType::CatmullRom,
+    )
+}
+
+/// timm `inception_v3.tf_in1k`: bicubic shortest edge 341 (crop_pct 0.875), center crop 299,
+/// scaled to `[-1, 1]`.
+pub const fn inception_v3() -> ImageConvert {
+    square(
+        ResizeMode::ShortestEdge,
+        341,
+        299,
+        HALF_MEAN,
+        HALF_STD,
+        FilterType::CatmullRom,
+    )
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum EfficientNet {
+    B0,
+    B1,
+    B2,
+    B3,
+    B4,
+    B5,
+    B6,
+    B7,
+}
+
+/// torchvision `EfficientNet_B*_Weights.IMAGENET1K_V1` transforms (shortest edge resize).
+pub const fn efficientnet(variant: EfficientNet) -> ImageConvert {
+    let (resize, crop, interpolation) = match variant {
+        EfficientNet::B0 => (256, 224, FilterType::CatmullRom),
+        EfficientNet::B1 => (256, 240, FilterType::CatmullRom),
+        EfficientNet::B2 => (288, 288, FilterType::CatmullRom),
+        EfficientNet::B3 => (320, 300, FilterType::CatmullRom),
+        EfficientNet::B4 => (384, 380, FilterType::CatmullRom),
+        EfficientNet::B5 => (456, 456, FilterType::CatmullRom),
+        EfficientNet::B6 => (528, 528, FilterType::CatmullRom),
+        EfficientNet::B7 => (600, 600, FilterType::CatmullRom),
+    };
+    square(
+        ResizeMode::ShortestEdge,
+        resize,
+        crop,
+        IMAGENET_DEFAULT_MEAN,
+        IMAGENET_DEFAULT_STD,
+        interpolation,
+    )
+}
+
+/// ultralytics YOLO: bilinear letterbox to 640 with gray (114) padding, scaled to `[0, 1]`.
+pub const fn yolo() -> ImageConvert {
+    LETTERBOX_DEFAULT_CONFIG
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// `openai/clip-vit-base-patch32/preprocessor_config.json`.
+    const CLIP_HF: &str = r#"{
+        "crop_size": 224, "do_center_crop": true, "do_normalize": true, "do_resize": true,
+        "feature_extractor_type": "CLIPFeatureExtractor",
+        "image_mean": [0.48145466, 0.4578275, 0.40821073],
+        "image_std": [0.26862954, 0.26130258, 0.27577711],
+        "resample": 3, "size": 224
+    }"#;
+
+    /// `openai/clip-vit-large-patch14-336/preprocessor_config.json`.
+    const CLIP_336_HF: &str = r#"{
+        "crop_size": 336, "do_center_crop": true, "do_normalize": true, "do_resize": true,
+        "feature_extractor_type": "CLIPFeatureExtractor",
+        "image_mean": [0.48145466, 0.4578275, 0.40821073],
+        "image_std": [0.26862954, 0.26130258, 0.27577711],
+        "resample": 3, "size": 336
+    }"#;
+
+    /// `facebook/convnext-base-224/preprocessor_config.json`.
+    const CONVNEXT_HF: &str = r#"{
+        "crop_pct": 0.875, "do_normalize": true, "do_resize": true,
+        "feature_extractor_type": "ConvNextFeatureExtractor",
+        "image_mean": [0.485, 0.456, 0.406], "image_std": [0.229, 0.224, 0.225],
+        "resample": 3, "size": 224
+    }"#;
+
+    /// `google/siglip-base-patch16-224/preprocessor_config.json`.
+    const SIGLIP_224_HF: &str = r#"{
+        "do_normalize": true, "do_rescale": true, "do_resize": true,
+        "image_mean": [0.5, 0.5, 0.5], "image_processor_type": "SiglipImageProcessor",
+        "image_std": [0.5, 0.5, 0.5], "processor_class": "SiglipProcessor",
+        "resample": 3, "rescale_factor": 0.00392156862745098,
+        "size": {"height": 224, "width": 224}
+    }"#;
+
+    /// `google/siglip-base-patch16-384/preprocessor_config.json`.
+    const SIGLIP_384_HF: &str = r#"{
+        "do_normalize": true, "do_rescale": true, "do_resize": true,
+        "image_mean": [0.5, 0.5, 0.5], "image_processor_type": "SiglipImageProcessor",
+        "image_std": [0.5, 0.5, 0.5], "processor_class": "SiglipProcessor",
+        "resample": 3, "rescale_factor": 0.00392156862745098,
+        "size": {"height": 384, "width": 384}
+    }"#;
+
+    /// `facebook/dinov2-base/preprocessor_config.json`.
+    const DINOV2_HF: &str = r#"{
+        "crop_size": {"height": 224, "width": 224}, "do_center_crop": true,
+        "do_convert_rgb": true, "do_normalize": true, "do_rescale": true, "do_resize": true,
+        "image_mean": [0.485, 0.456, 0.406], "image_processor_type": "BitImageProcessor",
+        "image_std": [0.229, 0.224, 0.225], "resample": 3,
+        "rescale_factor": 0.00392156862745098, "size": {"shortest_edge": 256}
+    }"#;
+
+    /// `timm/inception_v3.tf_in1k/config.json`.
+    const INCEPTION_V3_TIMM: &str = r#"{
+        "architecture": "inception_v3", "num_classes": 1000, "num_features": 2048,
+        "pretrained_cfg": {
+            "tag": "tf_in1k", "custom_load": false, "input_size": [3, 299, 299],
+            "fixed_input_size": false, "interpolation": "bicubic", "crop_pct": 0.875,
+            "crop_mode": "center", "mean": [0.5, 0.5, 0.5], "std": [0.5, 0.5, 0.5],
+            "num_classes": 1000, "pool_size": [8, 8],
+            "first_conv": "Conv2d_1a_3x3.conv", "classifier": "fc"
+        }
+    }"#;
+
+    fn hf(json: &str) -> ImageConvert {
+        ImageConvert::from_hf_preprocessor_json(json).unwrap()
+    }
+
+    /// torchvision's `ImageClassification` transform: shortest edge resize,
+    /// center crop and ImageNet mean/std.
+    fn torchvision(resize: u32, crop: u32, interpolation: FilterType) -> ImageConvert {
+        ImageConvert::builder()
+            .resize(resize, resize)
+            .resize_mode(ResizeMode::ShortestEdge)
+            .crop(crop, crop)
+            .interpolation(interpolation)
+            .mean(IMAGENET_DEFAULT_MEAN)
+            .std(IMAGENET_DEFAULT_STD)
+            .build()
+            .unwrap()
+    }
+
+    #[test]
+    fn clip_matches_hf_config() {
+        assert_eq!(clip(), hf(CLIP_HF));
+        assert_eq!(clip_336(), hf(CLIP_336_HF));
+    }
+
+    #[test]
+    fn siglip_matches_hf_config() {
+        assert_eq!(siglip_224(), hf(SIGLIP_224_HF));
+        assert_eq!(siglip_384(), hf(SIGLIP_384_HF));
+    }
+
+    #[test]
+    fn dinov2_matches_hf_config() {
+        assert_eq!(dinov2(), hf(DINOV2_HF));
+    }
+
+    #[test]
+    fn convnext_matches_hf_config() {
+        assert_eq!(convnext(), hf(CONVNEXT_HF));
+    }
+
+    #[test]
+    fn inception_v3_matches_timm_config() {
+        let expected = ImageConvert::from_timm_pretrained_cfg_json(INCEPTION_V3_TIMM).unwrap();
+        assert_eq!(inception_v3(), expected);
+    }
+
+    #[test]
+    fn efficientnet_matches_torchvision_weights() {
+        // `transforms` of the EfficientNet_B*_Weights.IMAGENET1K_V1 enums:
+        // partial(ImageClassification, crop_size, resize_size, interpolation)
+        let weights = [
+            (EfficientNet::B0, 224, 256),
+            (EfficientNet::B1, 240, 256),
+            (EfficientNet::B2, 288, 288),
+            (EfficientNet::B3, 300, 320),
+            (EfficientNet::B4, 380, 384),
+            (EfficientNet::B5, 456, 456),
+            (EfficientNet::B6, 528, 528),
+            (EfficientNet::B7, 600, 600),
+        ];
+        for (variant, crop_size, resize_size) in weights {
+            let expected = torchvision(resize_size, crop_size, FilterType::CatmullRom);
+            assert_eq!(efficientnet(variant), expected, "{variant:?}");
+        }
+    }
+
+    #[test]
+    fn yolo_matches_ultralytics_letterbox() {
+        // LetterBox((640, 640), auto=False) with cv2.INTER_LINEAR and value 114
+        let conv = yolo();
+        assert_eq!(conv.resize_mode, ResizeMode::Letterbox { stride: None });
+        assert_eq!((conv.crop_width, conv.crop_height), (640, 640));
+        assert_eq!(conv.interpolation, FilterType::Triangle);
+        assert_eq!(conv.fill, [114; 3]);
+        assert_eq!((conv.mean, conv.std), ([0.0; 3], [1.0; 3]));
+        assert_eq!(conv.input_scale, 1.0 / 255.0);
+    }
+}
@@ -1,25 +1,64 @@
 use image::DynamicImage;
 use ndarray::{Array3, Array4};
 
-use crate::cpu_tensor::{IMAGENET_DEFAULT_CONFIG, IMAGENET_DEFAULT_CONFIG_NO_CROP, ImageConvert};
+use crate::{
+    cpu_tensor::{IMAGENET_DEFAULT_CONFIG, IMAGENET_DEFAULT_CONFIG_NO_CROP, ImageConvert},
+    element::{QuantElement, QuantParams, TensorElement},
+    error::Result,
+    letterbox::LetterboxInfo,
+};
 
 pub trait Tensorizer {
     type BuildType;
-    fn new(
-        config: ImageConvert,
-    ) -> impl std::future::Future<Output = anyhow::Result<Self::BuildType>>;
-    fn default() -> impl std::future::Future<Output = anyhow::Result<Self::BuildType>> {
+    fn new(config: ImageConvert) -> impl std::future::Future<Output = Result<Self::BuildType>>;
+    fn default() -> impl std::future::Future<Output = Result<Self::BuildType>> {
         Self::new(IMAGENET_DEFAULT_CONFIG)
     }
-    fn default_no_crop() -> impl std::future::Future<Output = anyhow::Result<Self::BuildType>> {
+    fn default_no_crop() -> impl std::future::Future<Output = Result<Self::BuildType>> {
         Self::new(IMAGENET_DEFAULT_CONFIG_NO_CROP)
     }
+    /// The preprocessing this tensorizer applies.
+    fn config(&self) -> &ImageConvert;
     fn tensorize(
         &self,
         image: &DynamicImage,
-    ) -> impl std::future::Future<Output = anyhow::Result<Array3<f32>>>;
-    fn tensorize_batch(
+    ) -> impl std::future::Future<Output = Result<Array3<f32>>> {
+        self.tensorize_as::<f32>(image)
+    }
+    /// Tensorizes `image` directly into elements of type `T`, e.g. [`half::f16`].
+    fn tensorize_as<T: TensorElement>(
+        &self,
+        image: &DynamicImage,
+    ) -> impl std::future::Future<Output = Result<Array3<T>>>;
+    /// Tensorizes `image` into a quantized integer tensor, e.g. for int8 models.
+    /// The normalized values are quantized as they are written.
+    fn tensorize_quantized<Q: QuantElement>(
+        &self,
+        image: &DynamicImage,
+        params: QuantParams,
+    ) -> impl std::future::Future<Output = Result<Array3<Q>>>;
+    /// Tensorizes `image` and reports where it was placed in the tensor, so
+    /// predictions can be mapped back onto the original image.
+    fn tensorize_letterbox(
         &self,
         image: &DynamicImage,
-    ) -> impl std::future::Future<Output = anyhow::Result<Array4<f32>>>;
+    ) -> impl std::future::Future<Output = Result<(Array3<f32>, LetterboxInfo)>> {
+        async move {
+            let tensor = self.tensorize(image).await?;
+            let info = self.config().letterbox_info(image.width(), image.height());
+            Ok((tensor, info))
+        }
+    }
+    /// Tensorizes every image in `images` into a single `[N, C, H, W]` tensor.
+    fn tensorize_batch(
+        &self,
+        images: &[DynamicImage],
+    ) -> impl std::future::Future<Output = Result<Array4<f32>>> {
+        self.tensorize_batch_as::<f32>(images)
+    }
+    /// Tensorizes every image in `images` into a single tensor of type `T`.
+    fn tensorize_batch_as<T: TensorElement>(
+        &self,
+        images: &[DynamicImage],
+    ) -> impl std::future::Future<Output = Result<Array4<T>>>;
 }
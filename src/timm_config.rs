@@ -0,0 +1,185 @@
+//! timm `pretrained_cfg` support.
+
+use std::path::Path;
+
+use image::imageops::FilterType;
+use serde::Deserialize;
+
+use crate::{
+    cpu_tensor::{ImageConvert, ResizeMode},
+    error::{Result, TensorizeError},
+};
+
+const DEFAULT_CROP_PCT: f64 = 0.875;
+
+#[derive(Debug, Deserialize)]
+struct TimmPretrainedCfg {
+    input_size: [u32; 3],
+    #[serde(default = "default_interpolation")]
+    interpolation: String,
+    crop_pct: Option<f64>,
+    crop_mode: Option<String>,
+    mean: Vec<f32>,
+    std: Vec<f32>,
+}
+
+/// A hub `config.json` nests the preprocessing under `pretrained_cfg`,
+/// `model.pretrained_cfg` is the bare dictionary.
+#[derive(Debug, Deserialize)]
+#[serde(untagged)]
+enum TimmConfig {
+    Hub { pretrained_cfg: TimmPretrainedCfg },
+    Bare(TimmPretrainedCfg),
+}
+
+/// timm's `resolve_data_config` falls back to bicubic.
+fn default_interpolation() -> String {
+    "bicubic".to_string()
+}
+
+fn timm_filter(interpolation: &str) -> Result<FilterType> {
+    match interpolation {
+        "nearest" => Ok(FilterType::Nearest),
+        "bilinear" => Ok(FilterType::Triangle),
+        "bicubic" => Ok(FilterType::CatmullRom),
+        "lanczos" => Ok(FilterType::Lanczos3),
+        other => Err(TensorizeError::InvalidConfig(format!(
+            "unsupported timm interpolation {other:?}"
+        ))),
+    }
+}
+
+fn channels(name: &str, values: &[f32], channels: u32) -> Result<[f32; 3]> {
+    match *values {
+        [value] if channels == 1 => Ok([value; 3]),
+        [r, g, b] if channels == 3 => Ok([r, g, b]),
+        _ => Err(TensorizeError::InvalidConfig(format!(
+            "{name} has {} entries for {channels} input channels",
+            values.len()
+        ))),
+    }
+}
+
+impl ImageConvert {
+    /// Reads a timm hub `config.json` or a serialized `pretrained_cfg`.
+    pub fn from_timm_pretrained_cfg(path: impl AsRef<Path>) -> Result<ImageConvert> {
+        let json = std::fs::read_to_string(path)?;
+        ImageConvert::from_timm_pretrained_cfg_json(&json)
+    }
+
+    /// Parses a timm hub `config.json` or a serialized `pretrained_cfg`.
+    ///
+    /// Follows timm's eval transform: the image is resized to
+    /// `floor(input_size / crop_pct)` (keeping the aspect ratio unless
+    /// `crop_mode` is `squash`) and center cropped to `input_size`. With
+    /// `crop_mode` `border` the longest side is resized instead and the crop
+    /// is padded with the mean color.
+    pub fn from_timm_pretrained_cfg_json(json: &str) -> Result<ImageConvert> {
+        let cfg = match serde_json::from_str(json)? {
+            TimmConfig::Hub { pretrained_cfg } => pretrained_cfg,
+            TimmConfig::Bare(cfg) => cfg,
+        };
+
+        let [in_channels, crop_height, crop_width] = cfg.input_size;
+        let crop_pct = cfg.crop_pct.unwrap_or(DEFAULT_CROP_PCT);
+        if !(crop_pct > 0.0 && crop_pct <= 1.0) {
+            return Err(TensorizeError::InvalidConfig(format!(
+                "crop_pct {crop_pct} must be in (0, 1]"
+            )));
+        }
+        let scale = |size: u32| (size as f64 / crop_pct).floor() as u32;
+
+        let resize_mode = match cfg.crop_mode.as_deref().unwrap_or("center") {
+            // timm resizes the shortest edge for a square scale size and keeps
+            // the ratio otherwise
+            "center" if scale(crop_width) == scale(crop_height) => ResizeMode::ShortestEdge,
+            "center" => ResizeMode::Fill,
+            "squash" => ResizeMode::Exact,
+            "border" => ResizeMode::Fit,
+            other => {
+                return Err(TensorizeError::InvalidConfig(format!(
+                    "unsupported timm crop_mode {other:?}"
+                )));
+            }
+        };
+
+        let mean = channels("mean", &cfg.mean, in_channels)?;
+        let mut builder = ImageConvert::builder()
+            .channels(in_channels as u8)
+            .resize(scale(crop_width), scale(crop_height))
+            .resize_mode(resize_mode)
+            .crop(crop_width, crop_height)
+            .mean(mean)
+            .std(channels("std", &cfg.std, in_channels)?)
+            .interpolation(timm_filter(&cfg.interpolation)?);
+        if resize_mode == ResizeMode::Fit {
+            // timm's CenterCropOrPad fills with round(255 * mean)
+            builder = builder
+                .fill(mean.map(|value| (255.0 * value).round_ties_even().clamp(0.0, 255.0) as u8));
+        }
+        builder.build()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::cpu_tensor::IMAGENET_DEFAULT_MEAN;
+
+    fn parse(input_size: [u32; 3], extra: &str) -> Result<ImageConvert> {
+        ImageConvert::from_timm_pretrained_cfg_json(&format!(
+            r#"{{"input_size": {input_size:?}, {extra}
+                "mean": [0.485, 0.456, 0.406], "std": [0.229, 0.224, 0.225]}}"#
+        ))
+    }
+
+    #[test]
+    fn squash_resizes_exactly() {
+        let conv = parse([3, 224, 224], r#""crop_pct": 0.9, "crop_mode": "squash","#).unwrap();
+        assert_eq!(conv.resize_mode, ResizeMode::Exact);
+        assert_eq!((conv.width, conv.height), (248, 248));
+        assert_eq!((conv.crop_width, conv.crop_height), (224, 224));
+        // resolve_data_config defaults to bicubic
+        assert_eq!(conv.interpolation, FilterType::CatmullRom);
+    }
+
+    #[test]
+    fn border_pads_with_the_mean_color() {
+        let conv = parse([3, 224, 224], r#""crop_pct": 1.0, "crop_mode": "border","#).unwrap();
+        assert_eq!(conv.resize_mode, ResizeMode::Fit);
+        assert_eq!(conv.mean, IMAGENET_DEFAULT_MEAN);
+        assert_eq!(conv.fill, [124, 116, 104]);
+        // The longest side is scaled to 224 and the crop padded evenly
+        let info = conv.letterbox_info(640, 480);
+        assert_eq!((info.resized_width, info.resized_height), (224, 168));
+        assert_eq!((info.pad_x, info.pad_y), (0, 28));
+    }
+
+    #[test]
+    fn non_square_center_keeps_the_ratio() {
+        let conv = parse(
+            [3, 256, 320],
+            r#""crop_pct": 0.9, "interpolation": "bilinear","#,
+        )
+        .unwrap();
+        assert_eq!(conv.resize_mode, ResizeMode::Fill);
+        assert_eq!((conv.width, conv.height), (355, 284));
+        assert_eq!((conv.crop_width, conv.crop_height), (320, 256));
+        assert_eq!(conv.interpolation, FilterType::Triangle);
+        // ResizeKeepRatio rounds 500 / (375 / 284) = 378.67 up
+        let info = conv.letterbox_info(500, 375);
+        assert_eq!((info.resized_width, info.resized_height), (379, 284));
+        assert_eq!((info.pad_x, info.pad_y), (-29, -14));
+    }
+
+    #[test]
+    fn rejects_bad_crop_pct() {
+        for crop_pct in ["0.0", "-0.5", "1.5"] {
+            let conv = parse([3, 224, 224], &format!(r#""crop_pct": {crop_pct},"#));
+            assert!(
+                matches!(conv, Err(TensorizeError::InvalidConfig(_))),
+                "{crop_pct}"
+            );
+        }
+    }
+}